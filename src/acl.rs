@@ -0,0 +1,156 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use glob::Pattern;
+
+use crate::auth::CredentialStore;
+use crate::config::AclRule;
+
+struct CompiledRule {
+    users: Vec<String>,
+    groups: Vec<String>,
+    repositories: Vec<Pattern>,
+}
+
+pub struct RepositoryAcl {
+    rules: Vec<CompiledRule>,
+    store: Arc<CredentialStore>,
+}
+
+impl RepositoryAcl {
+    pub fn new(rules: &[AclRule], store: Arc<CredentialStore>) -> Result<RepositoryAcl, String> {
+        let mut compiled_rules = vec![];
+        for rule in rules {
+            let mut repositories = vec![];
+            for repository in &rule.repositories {
+                let pattern = Pattern::new(repository).map_err(|err| {
+                    format!("acl: invalid repository pattern {}: {}", repository, err)
+                })?;
+                repositories.push(pattern);
+            }
+
+            compiled_rules.push(CompiledRule {
+                users: rule.users.clone(),
+                groups: rule.groups.clone(),
+                repositories,
+            });
+        }
+
+        Ok(RepositoryAcl {
+            rules: compiled_rules,
+            store,
+        })
+    }
+
+    pub fn allowed_repositories(&self, user: &str, repositories: &[String]) -> Vec<String> {
+        if self.rules.is_empty() {
+            return repositories.to_vec();
+        }
+
+        let groups = self
+            .store
+            .user(user)
+            .map(|entry| entry.groups)
+            .unwrap_or_default();
+
+        let patterns = self
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.users.iter().any(|name| name == "*" || name == user)
+                    || rule.groups.iter().any(|group| groups.contains(group))
+            })
+            .flat_map(|rule| rule.repositories.iter())
+            .collect::<Vec<_>>();
+
+        repositories
+            .iter()
+            .filter(|repository| {
+                let name = Path::new(repository)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default();
+                patterns.iter().any(|pattern| pattern.matches(name))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserEntry;
+
+    type Rule<'r> = (&'r [&'r str], &'r [&'r str], &'r [&'r str]);
+
+    /// A store with the user alice in the group dev.
+    fn store() -> Arc<CredentialStore> {
+        Arc::new(CredentialStore::with_users(vec![UserEntry {
+            name: "alice".to_owned(),
+            md5: None,
+            scram: None,
+            groups: vec!["dev".to_owned()],
+        }]))
+    }
+
+    fn rules(rules: &[Rule]) -> Vec<AclRule> {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        rules
+            .iter()
+            .map(|(users, groups, repositories)| AclRule {
+                users: strings(users),
+                groups: strings(groups),
+                repositories: strings(repositories),
+            })
+            .collect()
+    }
+
+    fn allowed(acl_rules: &[Rule], user: &str) -> Vec<String> {
+        let acl = RepositoryAcl::new(&rules(acl_rules), store()).unwrap();
+        acl.allowed_repositories(user, &repositories())
+    }
+
+    fn repositories() -> Vec<String> {
+        ["/srv/git/gitql", "/srv/git/gitql-server", "/srv/git/linux"]
+            .iter()
+            .map(|repository| repository.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn no_rules_allow_every_repository() {
+        assert_eq!(allowed(&[], "bob"), repositories());
+    }
+
+    #[test]
+    fn globs_match_repository_names() {
+        let acl_rules: &[Rule] = &[(&["bob"], &[], &["gitql*"])];
+        assert_eq!(
+            allowed(acl_rules, "bob"),
+            vec!["/srv/git/gitql", "/srv/git/gitql-server"]
+        );
+        assert!(allowed(acl_rules, "carol").is_empty());
+    }
+
+    #[test]
+    fn globs_do_not_match_the_repository_path() {
+        assert!(allowed(&[(&["*"], &[], &["/srv/git/*", "git?ql"])], "bob").is_empty());
+    }
+
+    #[test]
+    fn rules_apply_to_every_user_and_to_groups() {
+        let acl_rules: &[Rule] = &[(&["*"], &[], &["linux"]), (&[], &["dev"], &["gitql-[s]*"])];
+        assert_eq!(
+            allowed(acl_rules, "alice"),
+            vec!["/srv/git/gitql-server", "/srv/git/linux"]
+        );
+        assert_eq!(allowed(acl_rules, "bob"), vec!["/srv/git/linux"]);
+    }
+
+    #[test]
+    fn invalid_globs_are_rejected() {
+        let acl = RepositoryAcl::new(&rules(&[(&["*"], &[], &["gitql-[s"])]), store());
+        assert!(acl.is_err());
+    }
+}
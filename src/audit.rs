@@ -0,0 +1,165 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::config::AuditConfig;
+
+#[derive(Serialize)]
+pub struct AuditRecord<'a> {
+    pub timestamp: String,
+    pub user: &'a str,
+    pub client_addr: String,
+    pub database: Option<&'a str>,
+    pub application_name: Option<&'a str>,
+    pub query: String,
+    pub repositories: Vec<String>,
+    pub rows: Option<usize>,
+    pub error: Option<String>,
+}
+
+struct AuditFile {
+    file: File,
+    size: u64,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: Mutex<AuditFile>,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Result<AuditLog, String> {
+        let file = open_audit_file(&config.path)?;
+        Ok(AuditLog {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes,
+            keep: config.keep,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("cannot serialize audit record: {}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let mut audit_file = self.file.lock().unwrap();
+        if self.max_bytes > 0 && audit_file.size + line.len() as u64 > self.max_bytes {
+            match self.rotate() {
+                Ok(file) => *audit_file = file,
+                Err(err) => tracing::error!("{}", err),
+            }
+        }
+
+        match audit_file.file.write_all(&line) {
+            Ok(()) => audit_file.size += line.len() as u64,
+            Err(err) => tracing::error!("cannot write audit log {}: {}", self.path.display(), err),
+        }
+    }
+
+    fn rotate(&self) -> Result<AuditFile, String> {
+        for index in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1)).map_err(|err| {
+                    format!("cannot rotate audit log {}: {}", from.display(), err)
+                })?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1)).map_err(|err| {
+                format!("cannot rotate audit log {}: {}", self.path.display(), err)
+            })?;
+        } else {
+            fs::remove_file(&self.path).map_err(|err| {
+                format!("cannot rotate audit log {}: {}", self.path.display(), err)
+            })?;
+        }
+
+        open_audit_file(&self.path)
+    }
+}
+
+pub fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Collapses whitespace and replaces string and numeric literals with `?`
+pub fn normalize_query(query: &str) -> String {
+    let mut normalized = String::with_capacity(query.len());
+    let mut chars = query.trim().trim_end_matches(';').chars().peekable();
+    let mut previous_is_word = false;
+
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            while let Some(next) = chars.next() {
+                if next == c {
+                    if chars.peek() == Some(&c) {
+                        chars.next();
+                        continue;
+                    }
+                    break;
+                }
+            }
+            normalized.push('?');
+            previous_is_word = false;
+            continue;
+        }
+
+        if c.is_ascii_digit() && !previous_is_word {
+            while chars
+                .peek()
+                .map_or(false, |next| next.is_ascii_digit() || *next == '.')
+            {
+                chars.next();
+            }
+            normalized.push('?');
+            continue;
+        }
+
+        if c.is_whitespace() {
+            while chars.peek().map_or(false, |next| next.is_whitespace()) {
+                chars.next();
+            }
+            normalized.push(' ');
+            previous_is_word = false;
+            continue;
+        }
+
+        previous_is_word = c.is_alphanumeric() || c == '_';
+        normalized.push(c);
+    }
+
+    normalized
+}
+
+fn open_audit_file(path: &Path) -> Result<AuditFile, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("cannot open audit log {}: {}", path.display(), err))?;
+    let size = file
+        .metadata()
+        .map_err(|err| format!("cannot open audit log {}: {}", path.display(), err))?
+        .len();
+    Ok(AuditFile { file, size })
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pgwire::api::auth::scram::gen_salted_password;
+use serde::Deserialize;
+
+const SCRAM_PREFIX: &str = "SCRAM-SHA-256$";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile {
+    #[serde(default)]
+    users: Vec<UserEntry>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserEntry {
+    pub name: String,
+    pub md5: Option<String>,
+    pub scram: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl UserEntry {
+    pub fn scram_secret(&self) -> Option<ScramSecret> {
+        self.scram
+            .as_deref()
+            .and_then(|secret| parse_scram_secret(secret).ok())
+    }
+}
+
+pub struct ScramSecret {
+    pub iterations: usize,
+    pub salt: Vec<u8>,
+    pub salted_password: Vec<u8>,
+}
+
+pub struct CredentialStore {
+    path: PathBuf,
+    users: RwLock<HashMap<String, UserEntry>>,
+}
+
+impl CredentialStore {
+    pub fn load(path: &Path) -> Result<CredentialStore, String> {
+        let users = read_users_file(path)?;
+        Ok(CredentialStore {
+            path: path.to_path_buf(),
+            users: RwLock::new(users),
+        })
+    }
+
+    pub fn reload(&self) -> Result<usize, String> {
+        let users = read_users_file(&self.path)?;
+        let count = users.len();
+        *self.users.write().unwrap() = users;
+        Ok(count)
+    }
+
+    pub fn user(&self, name: &str) -> Option<UserEntry> {
+        self.users.read().unwrap().get(name).cloned()
+    }
+
+    /// A store holding `users`, for tests that need no users file.
+    #[cfg(test)]
+    pub fn with_users(users: Vec<UserEntry>) -> CredentialStore {
+        let users = users
+            .into_iter()
+            .map(|user| (user.name.clone(), user))
+            .collect();
+        CredentialStore {
+            path: PathBuf::new(),
+            users: RwLock::new(users),
+        }
+    }
+}
+
+pub fn hash_md5_secret(user: &str, password: &str) -> String {
+    format!("md5{:x}", md5::compute(format!("{}{}", password, user)))
+}
+
+pub fn hash_scram_secret(password: &str, iterations: usize) -> String {
+    let salt = rand::random::<[u8; 16]>();
+    let salted_password = gen_salted_password(password, &salt, iterations);
+    format!(
+        "{}{}:{}${}",
+        SCRAM_PREFIX,
+        iterations,
+        STANDARD.encode(salt),
+        STANDARD.encode(salted_password)
+    )
+}
+
+fn parse_scram_secret(secret: &str) -> Result<ScramSecret, String> {
+    let format_error = || {
+        "scram secret must look like SCRAM-SHA-256$<iterations>:<salt>$<salted password>".to_owned()
+    };
+
+    let secret = secret.strip_prefix(SCRAM_PREFIX).ok_or_else(format_error)?;
+    let (parameters, salted_password) = secret.split_once('$').ok_or_else(format_error)?;
+    let (iterations, salt) = parameters.split_once(':').ok_or_else(format_error)?;
+
+    let iterations = iterations.parse::<usize>().map_err(|_| format_error())?;
+    let salt = STANDARD.decode(salt).map_err(|_| format_error())?;
+    let salted_password = STANDARD
+        .decode(salted_password)
+        .map_err(|_| format_error())?;
+    if iterations == 0 || salt.is_empty() || salted_password.len() != 32 {
+        return Err(format_error());
+    }
+
+    Ok(ScramSecret {
+        iterations,
+        salt,
+        salted_password,
+    })
+}
+
+fn read_users_file(path: &Path) -> Result<HashMap<String, UserEntry>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("cannot read users file {}: {}", path.display(), err))?;
+    let users_file: UsersFile = toml::from_str(&content)
+        .map_err(|err| format!("invalid users file {}: {}", path.display(), err))?;
+
+    let mut users = HashMap::new();
+    for user in users_file.users {
+        if user.name.is_empty() {
+            return Err(format!("users file {}: empty user name", path.display()));
+        }
+
+        if user.md5.is_none() && user.scram.is_none() {
+            return Err(format!(
+                "users file {}: user {} has neither an md5 nor a scram secret",
+                path.display(),
+                user.name
+            ));
+        }
+
+        if user.md5.as_deref().map_or(false, |md5| !is_md5_secret(md5)) {
+            return Err(format!(
+                "users file {}: md5 secret of user {} must be \"md5\" followed by 32 hex digits",
+                path.display(),
+                user.name
+            ));
+        }
+
+        if let Some(Err(err)) = user.scram.as_deref().map(parse_scram_secret) {
+            return Err(format!(
+                "users file {}: user {}: {}",
+                path.display(),
+                user.name,
+                err
+            ));
+        }
+
+        if users.contains_key(&user.name) {
+            return Err(format!(
+                "users file {}: user {} is defined more than once",
+                path.display(),
+                user.name
+            ));
+        }
+
+        users.insert(user.name.clone(), user);
+    }
+
+    Ok(users)
+}
+
+fn is_md5_secret(secret: &str) -> bool {
+    secret.len() == 35
+        && secret.starts_with("md5")
+        && secret[3..].chars().all(|c| c.is_ascii_hexdigit())
+}
@@ -0,0 +1,263 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::Sink;
+use pgwire::api::auth::md5pass::{
+    MakeMd5PasswordAuthStartupHandler, Md5PasswordAuthStartupHandler,
+};
+use pgwire::api::auth::scram::{MakeSASLScramAuthStartupHandler, SASLScramAuthStartupHandler};
+use pgwire::api::auth::{
+    finish_authentication, save_startup_parameters_to_metadata, AuthSource,
+    DefaultServerParameterProvider, LoginInfo, Password, StartupHandler,
+};
+use pgwire::api::{ClientInfo, MakeHandler, PgWireConnectionState, METADATA_USER};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+
+use crate::config::AuthMethod;
+use crate::metrics;
+use crate::session::SessionRegistry;
+
+pub use credential_store::{hash_md5_secret, hash_scram_secret, CredentialStore, UserEntry};
+pub use peer::PeerAuthenticator;
+
+mod credential_store;
+mod peer;
+
+pub struct CredentialAuthSource {
+    store: Arc<CredentialStore>,
+    method: AuthMethod,
+    scram_iterations: usize,
+}
+
+impl CredentialAuthSource {
+    pub fn new(
+        store: Arc<CredentialStore>,
+        method: AuthMethod,
+        scram_iterations: usize,
+    ) -> CredentialAuthSource {
+        CredentialAuthSource {
+            store,
+            method,
+            scram_iterations,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthSource for CredentialAuthSource {
+    async fn get_password(&self, login_info: &LoginInfo) -> PgWireResult<Password> {
+        let user = login_info
+            .user()
+            .as_ref()
+            .map(|user| user.to_string())
+            .unwrap_or_default();
+        tracing::debug!(user = %user, host = %login_info.host(), "authenticating");
+
+        let entry = match self.store.user(&user) {
+            Some(entry) => entry,
+            None => return Err(authentication_failed(&user)),
+        };
+
+        match self.method {
+            AuthMethod::Md5 => {
+                let md5 = match &entry.md5 {
+                    Some(md5) => md5,
+                    None => return Err(authentication_failed(&user)),
+                };
+
+                let salt = rand::random::<[u8; 4]>().to_vec();
+                let mut salted_secret = md5[3..].as_bytes().to_vec();
+                salted_secret.extend_from_slice(&salt);
+                let hash_password = format!("md5{:x}", md5::compute(salted_secret));
+
+                Ok(Password::new(Some(salt), hash_password.as_bytes().to_vec()))
+            }
+            AuthMethod::ScramSha256 => {
+                let secret = match entry.scram_secret() {
+                    Some(secret) => secret,
+                    None => return Err(authentication_failed(&user)),
+                };
+
+                if secret.iterations != self.scram_iterations {
+                    tracing::error!(
+                        user = %user,
+                        "scram secret uses {} iterations but auth.scram_iterations is {}",
+                        secret.iterations,
+                        self.scram_iterations
+                    );
+                    return Err(authentication_failed(&user));
+                }
+
+                Ok(Password::new(Some(secret.salt), secret.salted_password))
+            }
+        }
+    }
+}
+
+type Parameters = DefaultServerParameterProvider;
+
+enum MakePasswordStartupHandler {
+    Md5(MakeMd5PasswordAuthStartupHandler<CredentialAuthSource, Parameters>),
+    Scram(MakeSASLScramAuthStartupHandler<CredentialAuthSource, Parameters>),
+}
+
+pub struct MakeGitQLStartupHandler {
+    store: Arc<CredentialStore>,
+    password_handler: MakePasswordStartupHandler,
+    peer_authenticator: Option<Arc<PeerAuthenticator>>,
+    sessions: Arc<SessionRegistry>,
+    parameters: Arc<Parameters>,
+}
+
+impl MakeGitQLStartupHandler {
+    pub fn new(
+        store: Arc<CredentialStore>,
+        method: AuthMethod,
+        scram_iterations: usize,
+        peer_authenticator: Option<PeerAuthenticator>,
+        sessions: Arc<SessionRegistry>,
+        parameters: Arc<Parameters>,
+    ) -> MakeGitQLStartupHandler {
+        let auth_source = Arc::new(CredentialAuthSource::new(
+            store.clone(),
+            method,
+            scram_iterations,
+        ));
+        let password_handler = match method {
+            AuthMethod::Md5 => MakePasswordStartupHandler::Md5(
+                MakeMd5PasswordAuthStartupHandler::new(auth_source, parameters.clone()),
+            ),
+            AuthMethod::ScramSha256 => {
+                let mut handler =
+                    MakeSASLScramAuthStartupHandler::new(auth_source, parameters.clone());
+                handler.set_iterations(scram_iterations);
+                MakePasswordStartupHandler::Scram(handler)
+            }
+        };
+
+        MakeGitQLStartupHandler {
+            store,
+            password_handler,
+            peer_authenticator: peer_authenticator.map(Arc::new),
+            sessions,
+            parameters,
+        }
+    }
+}
+
+impl MakeHandler for MakeGitQLStartupHandler {
+    type Handler = Arc<GitQLStartupHandler>;
+
+    fn make(&self) -> Self::Handler {
+        let password_handler = match &self.password_handler {
+            MakePasswordStartupHandler::Md5(make) => PasswordStartupHandler::Md5(make.make()),
+            MakePasswordStartupHandler::Scram(make) => PasswordStartupHandler::Scram(make.make()),
+        };
+
+        Arc::new(GitQLStartupHandler {
+            store: self.store.clone(),
+            password_handler,
+            peer_authenticator: self.peer_authenticator.clone(),
+            sessions: self.sessions.clone(),
+            parameters: self.parameters.clone(),
+            password_messages: AtomicUsize::new(0),
+        })
+    }
+}
+
+enum PasswordStartupHandler {
+    Md5(Arc<Md5PasswordAuthStartupHandler<CredentialAuthSource, Parameters>>),
+    Scram(Arc<SASLScramAuthStartupHandler<CredentialAuthSource, Parameters>>),
+}
+
+pub struct GitQLStartupHandler {
+    store: Arc<CredentialStore>,
+    password_handler: PasswordStartupHandler,
+    peer_authenticator: Option<Arc<PeerAuthenticator>>,
+    sessions: Arc<SessionRegistry>,
+    parameters: Arc<Parameters>,
+    password_messages: AtomicUsize,
+}
+
+#[async_trait]
+impl StartupHandler for GitQLStartupHandler {
+    async fn on_startup<C>(
+        &self,
+        client: &mut C,
+        message: PgWireFrontendMessage,
+    ) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        if let PgWireFrontendMessage::Startup(ref startup) = message {
+            if let Some(session) = self.sessions.session(&client.socket_addr()) {
+                client.set_pid_and_secret_key(session.pid, session.secret);
+            }
+
+            let peer_user = self
+                .peer_authenticator
+                .as_ref()
+                .and_then(|peer_authenticator| {
+                    peer_authenticator.server_user(&client.socket_addr())
+                });
+
+            if let Some(peer_user) = peer_user {
+                save_startup_parameters_to_metadata(client, startup);
+                let user = client
+                    .metadata()
+                    .get(METADATA_USER)
+                    .cloned()
+                    .unwrap_or_default();
+                // Like a password login, a peer login needs an entry in the
+                // users file.
+                if user != peer_user || self.store.user(&user).is_none() {
+                    metrics::AUTH_FAILURES.with_label_values(&["peer"]).inc();
+                    return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "FATAL".to_owned(),
+                        "28000".to_owned(),
+                        format!("peer authentication failed for user \"{}\"", user),
+                    ))));
+                }
+
+                finish_authentication(client, self.parameters.as_ref()).await;
+                return Ok(());
+            }
+        }
+
+        let is_password_message =
+            matches!(message, PgWireFrontendMessage::PasswordMessageFamily(_));
+        let (method, final_message, result) = match &self.password_handler {
+            PasswordStartupHandler::Md5(handler) => {
+                ("md5", 1, handler.on_startup(client, message).await)
+            }
+            PasswordStartupHandler::Scram(handler) => (
+                "scram-sha-256",
+                2,
+                handler.on_startup(client, message).await,
+            ),
+        };
+
+        if is_password_message {
+            let password_messages = self.password_messages.fetch_add(1, Ordering::SeqCst) + 1;
+            let authenticated = matches!(client.state(), PgWireConnectionState::ReadyForQuery);
+            if result.is_err() || (password_messages >= final_message && !authenticated) {
+                metrics::AUTH_FAILURES.with_label_values(&[method]).inc();
+            }
+        }
+
+        result
+    }
+}
+
+fn authentication_failed(user: &str) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "FATAL".to_owned(),
+        "28P01".to_owned(),
+        format!("password authentication failed for user \"{}\"", user),
+    )))
+}
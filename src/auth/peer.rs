@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::unix_socket::UnixPeers;
+
+pub struct PeerAuthenticator {
+    peers: Arc<UnixPeers>,
+    user_map: HashMap<String, String>,
+}
+
+impl PeerAuthenticator {
+    pub fn new(peers: Arc<UnixPeers>, user_map: HashMap<String, String>) -> PeerAuthenticator {
+        PeerAuthenticator { peers, user_map }
+    }
+
+    pub fn server_user(&self, addr: &SocketAddr) -> Option<String> {
+        let os_user = self.peers.os_user(addr)?;
+        match self.user_map.get(&os_user) {
+            Some(server_user) => Some(server_user.clone()),
+            None => Some(os_user),
+        }
+    }
+}
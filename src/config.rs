@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use tracing::Level;
+
+/// PostgreSQL wire protocol server for GitQL
+#[derive(Parser)]
+#[command(name = "gql-server", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path of the TOML configuration file
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on, can be repeated
+    #[arg(short, long, value_name = "ADDR")]
+    pub listen: Vec<SocketAddr>,
+
+    /// Directory containing the git repositories to serve, can be repeated
+    #[arg(short, long = "repositories", value_name = "DIR")]
+    pub repositories: Vec<String>,
+
+    /// Users file holding the hashed credentials
+    #[arg(long, value_name = "FILE")]
+    pub users_file: Option<PathBuf>,
+
+    /// Password authentication method
+    #[arg(long, value_name = "METHOD")]
+    pub auth_method: Option<AuthMethod>,
+
+    /// PEM file with the TLS certificate chain
+    #[arg(long, value_name = "FILE")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the TLS private key
+    #[arg(long, value_name = "FILE")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Reject connections that do not negotiate TLS
+    #[arg(long)]
+    pub require_tls: bool,
+
+    /// Directory in which the .s.PGSQL.<port> unix socket is created
+    #[arg(long, value_name = "DIR")]
+    pub unix_socket_dir: Option<PathBuf>,
+
+    /// Version reported to clients in the server_version parameter
+    #[arg(long, value_name = "VERSION")]
+    pub server_version: Option<String>,
+
+    /// Maximum number of concurrent sessions
+    #[arg(long, value_name = "N")]
+    pub max_connections: Option<usize>,
+
+    /// Close sessions idle for longer than this many seconds, 0 disables
+    #[arg(long, value_name = "SECONDS")]
+    pub idle_timeout: Option<u64>,
+
+    /// Default statement_timeout in milliseconds, 0 disables
+    #[arg(long, value_name = "MILLISECONDS")]
+    pub statement_timeout: Option<u64>,
+
+    /// Write the query audit log to this file
+    #[arg(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Address of the HTTP listener serving Prometheus /metrics
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Log level (trace, debug, info, warn, error)
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Log output format
+    #[arg(long, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Read a password from stdin and print the matching users file entry
+    HashPassword {
+        /// Name of the user
+        user: String,
+
+        /// PBKDF2 iterations of the SCRAM-SHA-256 secret
+        #[arg(long, default_value_t = 4096)]
+        iterations: usize,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub repositories: RepositoriesConfig,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+    pub unix_socket: UnixSocketConfig,
+    pub acl: Vec<AclRule>,
+    pub limits: LimitsConfig,
+    pub query_limits: QueryLimitsConfig,
+    pub metrics: MetricsConfig,
+    pub audit: AuditConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<SocketAddr>,
+    pub server_version: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 5321))],
+            server_version: String::from("15"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepositoriesConfig {
+    pub roots: Vec<String>,
+}
+
+impl Default for RepositoriesConfig {
+    fn default() -> Self {
+        RepositoriesConfig {
+            roots: vec![String::from(".")],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub users_file: Option<PathBuf>,
+    pub method: AuthMethod,
+    pub scram_iterations: usize,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            users_file: None,
+            method: AuthMethod::Md5,
+            scram_iterations: 4096,
+        }
+    }
+}
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthMethod {
+    #[serde(rename = "md5")]
+    #[value(name = "md5")]
+    Md5,
+    #[serde(rename = "scram-sha-256")]
+    #[value(name = "scram-sha-256")]
+    ScramSha256,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub require: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnixSocketConfig {
+    pub directory: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub peer_auth: bool,
+    pub peer_map: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclRule {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub repositories: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections: usize,
+    pub idle_timeout_secs: u64,
+    pub shutdown_grace_secs: u64,
+    pub statement_timeout_ms: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_connections: 100,
+            idle_timeout_secs: 0,
+            shutdown_grace_secs: 30,
+            statement_timeout_ms: 0,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        match self.statement_timeout_ms {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLimitsConfig {
+    pub max_rows: u64,
+    pub max_commits: u64,
+    pub max_diff_bytes: u64,
+    pub max_memory_bytes: u64,
+    pub users: HashMap<String, UserQueryLimits>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserQueryLimits {
+    pub max_rows: Option<u64>,
+    pub max_commits: Option<u64>,
+    pub max_diff_bytes: Option<u64>,
+    pub max_memory_bytes: Option<u64>,
+}
+
+/// Limits applied to a single query, 0 means unlimited
+#[derive(Clone, Copy, Default)]
+pub struct QueryLimits {
+    pub max_rows: u64,
+    pub max_commits: u64,
+    pub max_diff_bytes: u64,
+    pub max_memory_bytes: u64,
+}
+
+impl QueryLimitsConfig {
+    pub fn for_user(&self, user: &str) -> QueryLimits {
+        let defaults = QueryLimits {
+            max_rows: self.max_rows,
+            max_commits: self.max_commits,
+            max_diff_bytes: self.max_diff_bytes,
+            max_memory_bytes: self.max_memory_bytes,
+        };
+
+        match self.users.get(user) {
+            Some(overrides) => QueryLimits {
+                max_rows: overrides.max_rows.unwrap_or(defaults.max_rows),
+                max_commits: overrides.max_commits.unwrap_or(defaults.max_commits),
+                max_diff_bytes: overrides.max_diff_bytes.unwrap_or(defaults.max_diff_bytes),
+                max_memory_bytes: overrides
+                    .max_memory_bytes
+                    .unwrap_or(defaults.max_memory_bytes),
+            },
+            None => defaults,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: false,
+            path: PathBuf::from("audit.jsonl"),
+            max_bytes: 100 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: String::from("info"),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    #[serde(rename = "text")]
+    #[value(name = "text")]
+    Text,
+    #[serde(rename = "json")]
+    #[value(name = "json")]
+    Json,
+}
+
+impl LoggingConfig {
+    pub fn level(&self) -> Level {
+        self.level.parse().unwrap_or(Level::INFO)
+    }
+}
+
+impl UnixSocketConfig {
+    pub fn port(&self, listen: &[SocketAddr]) -> u16 {
+        self.port
+            .unwrap_or_else(|| listen.first().map_or(5432, |addr| addr.port()))
+    }
+}
+
+impl Config {
+    pub fn load(cli: &Cli) -> Result<Config, String> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+
+        if !cli.listen.is_empty() {
+            config.server.listen = cli.listen.clone();
+        }
+
+        if !cli.repositories.is_empty() {
+            config.repositories.roots = cli.repositories.clone();
+        }
+
+        if let Some(users_file) = &cli.users_file {
+            config.auth.users_file = Some(users_file.clone());
+        }
+
+        if let Some(auth_method) = cli.auth_method {
+            config.auth.method = auth_method;
+        }
+
+        if let Some(tls_cert) = &cli.tls_cert {
+            config.tls.cert = Some(tls_cert.clone());
+        }
+
+        if let Some(tls_key) = &cli.tls_key {
+            config.tls.key = Some(tls_key.clone());
+        }
+
+        if cli.require_tls {
+            config.tls.require = true;
+        }
+
+        if let Some(unix_socket_dir) = &cli.unix_socket_dir {
+            config.unix_socket.directory = Some(unix_socket_dir.clone());
+        }
+
+        if let Some(server_version) = &cli.server_version {
+            config.server.server_version = server_version.clone();
+        }
+
+        if let Some(max_connections) = cli.max_connections {
+            config.limits.max_connections = max_connections;
+        }
+
+        if let Some(idle_timeout) = cli.idle_timeout {
+            config.limits.idle_timeout_secs = idle_timeout;
+        }
+
+        if let Some(statement_timeout) = cli.statement_timeout {
+            config.limits.statement_timeout_ms = statement_timeout;
+        }
+
+        if let Some(audit_log) = &cli.audit_log {
+            config.audit.enabled = true;
+            config.audit.path = audit_log.clone();
+        }
+
+        if let Some(metrics_listen) = cli.metrics_listen {
+            config.metrics.listen = Some(metrics_listen);
+        }
+
+        if let Some(log_level) = &cli.log_level {
+            config.logging.level = log_level.clone();
+        }
+
+        if let Some(log_format) = cli.log_format {
+            config.logging.format = log_format;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("cannot read config file {}: {}", path.display(), err))?;
+        toml::from_str(&content)
+            .map_err(|err| format!("invalid config file {}: {}", path.display(), err))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.server.listen.is_empty() {
+            return Err("server.listen must contain at least one address".to_owned());
+        }
+
+        if self.server.server_version.trim().is_empty() {
+            return Err("server.server_version must not be empty".to_owned());
+        }
+
+        if self.repositories.roots.is_empty() {
+            return Err("repositories.roots must contain at least one directory".to_owned());
+        }
+
+        for root in &self.repositories.roots {
+            if !Path::new(root).is_dir() {
+                return Err(format!(
+                    "repositories.roots: {} is not a readable directory",
+                    root
+                ));
+            }
+        }
+
+        match &self.auth.users_file {
+            Some(users_file) if !users_file.is_file() => {
+                return Err(format!(
+                    "auth.users_file: {} is not a readable file",
+                    users_file.display()
+                ));
+            }
+            Some(_) => {}
+            None => return Err("auth.users_file is required".to_owned()),
+        }
+
+        if self.auth.scram_iterations == 0 {
+            return Err("auth.scram_iterations must be greater than zero".to_owned());
+        }
+
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !path.is_file() {
+                        return Err(format!("tls: {} is not a readable file", path.display()));
+                    }
+                }
+            }
+            (None, None) if self.tls.require => {
+                return Err("tls.require needs tls.cert and tls.key".to_owned());
+            }
+            (None, None) => {}
+            _ => return Err("tls.cert and tls.key must be set together".to_owned()),
+        }
+
+        match &self.unix_socket.directory {
+            Some(directory) if !directory.is_dir() => {
+                return Err(format!(
+                    "unix_socket.directory: {} is not a directory",
+                    directory.display()
+                ));
+            }
+            None if self.unix_socket.peer_auth => {
+                return Err("unix_socket.peer_auth needs unix_socket.directory".to_owned());
+            }
+            _ => {}
+        }
+
+        for (index, rule) in self.acl.iter().enumerate() {
+            if rule.users.is_empty() && rule.groups.is_empty() {
+                return Err(format!("acl rule {} must name users or groups", index + 1));
+            }
+        }
+
+        if self.limits.max_connections == 0 {
+            return Err("limits.max_connections must be greater than zero".to_owned());
+        }
+
+        if self.logging.level.parse::<Level>().is_err() {
+            return Err(format!(
+                "logging.level: unknown level {}, expected one of trace, debug, info, warn, error",
+                self.logging.level
+            ));
+        }
+
+        Ok(())
+    }
+}
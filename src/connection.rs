@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use pgwire::api::auth::StartupHandler;
+use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
+use pgwire::api::store::PortalStore;
+use pgwire::api::{
+    ClientInfo, ClientInfoHolder, ClientPortalStore, PgWireConnectionState, DEFAULT_NAME,
+};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::extendedquery::TARGET_TYPE_BYTE_PORTAL;
+use pgwire::messages::response::{ReadyForQuery, TransactionStatus};
+use pgwire::messages::startup::ParameterStatus;
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use pgwire::tokio::PgWireMessageServerCodec;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio_util::codec::{Framed, FramedParts};
+
+use crate::git_backend::{ClientRelease, GitQLBackend};
+use crate::session::CloseReason;
+
+type PgWireSocket<S> = Framed<S, PgWireMessageServerCodec>;
+
+/// Names of the prepared statements and portals the client created, so that
+/// DEALLOCATE ALL, CLOSE ALL and DISCARD ALL can remove every one of them.
+#[derive(Default)]
+struct ClientObjects {
+    statements: HashSet<String>,
+    portals: HashSet<String>,
+}
+
+impl ClientObjects {
+    fn record(&mut self, message: &PgWireFrontendMessage) {
+        let name = |name: &Option<String>| name.as_deref().unwrap_or(DEFAULT_NAME).to_owned();
+        match message {
+            PgWireFrontendMessage::Parse(parse) => {
+                self.statements.insert(name(parse.name()));
+            }
+            PgWireFrontendMessage::Bind(bind) => {
+                self.portals.insert(name(bind.portal_name()));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Runs the frontend/backend protocol on a connection whose SSL negotiation
+/// is over. `read_buf` holds bytes of the startup packet already read from
+/// `socket`. When the server closes the session through `close`, the client
+/// is sent the reason as a FATAL error, even in the middle of a query.
+pub async fn process_connection<S, A>(
+    socket: S,
+    addr: SocketAddr,
+    is_secure: bool,
+    read_buf: BytesMut,
+    mut close: watch::Receiver<Option<CloseReason>>,
+    authenticator: Arc<A>,
+    processor: Arc<GitQLBackend>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    A: StartupHandler,
+{
+    let codec = PgWireMessageServerCodec::new(ClientInfoHolder::new(addr, is_secure));
+    let mut parts = FramedParts::new::<PgWireBackendMessage>(socket, codec);
+    parts.read_buf = read_buf;
+    let mut socket = Framed::from_parts(parts);
+    let mut objects = ClientObjects::default();
+
+    loop {
+        let message = tokio::select! {
+            message = socket.next() => message,
+            Ok(()) = close.changed() => break,
+        };
+        let message = match message {
+            Some(Ok(message)) => message,
+            Some(Err(err)) => {
+                tracing::debug!("closing connection after a protocol error: {}", err);
+                return;
+            }
+            None => return,
+        };
+
+        if matches!(message, PgWireFrontendMessage::Terminate(_)) {
+            return;
+        }
+
+        if matches!(socket.state(), PgWireConnectionState::AwaitingSync) {
+            if !matches!(message, PgWireFrontendMessage::Sync(_)) {
+                continue;
+            }
+            socket.set_state(PgWireConnectionState::ReadyForQuery);
+        }
+
+        objects.record(&message);
+        let is_extended_query = is_extended_query(&message);
+        let cursor_end = cursor_end(&message);
+        let result = tokio::select! {
+            result = process_message(message, &mut socket, &authenticator, &processor) => result,
+            Ok(()) = close.changed() => break,
+        };
+        if let Err(err) = result {
+            if !report_error(&mut socket, err, is_extended_query).await {
+                return;
+            }
+        }
+        close_cursors(&socket, &processor, cursor_end);
+        release_client_objects(&socket, &processor, &mut objects);
+        if !report_parameters(&mut socket, &processor).await {
+            return;
+        }
+    }
+
+    let reason = *close.borrow();
+    if let Some(reason) = reason {
+        let error_info = ErrorInfo::new(
+            "FATAL".to_owned(),
+            reason.code().to_owned(),
+            reason.message().to_owned(),
+        );
+        let _ = socket
+            .send(PgWireBackendMessage::ErrorResponse(error_info.into()))
+            .await;
+        let _ = socket.close().await;
+    }
+}
+
+async fn process_message<S, A>(
+    message: PgWireFrontendMessage,
+    socket: &mut PgWireSocket<S>,
+    authenticator: &Arc<A>,
+    processor: &Arc<GitQLBackend>,
+) -> PgWireResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    A: StartupHandler,
+{
+    if matches!(
+        socket.state(),
+        PgWireConnectionState::AwaitingStartup | PgWireConnectionState::AuthenticationInProgress
+    ) {
+        return authenticator.on_startup(socket, message).await;
+    }
+
+    match message {
+        PgWireFrontendMessage::Query(query) => processor.on_query(socket, query).await,
+        PgWireFrontendMessage::Parse(parse) => processor.on_parse(socket, parse).await,
+        PgWireFrontendMessage::Bind(bind) => processor.on_bind(socket, bind).await,
+        PgWireFrontendMessage::Execute(execute) => processor.on_execute(socket, execute).await,
+        PgWireFrontendMessage::Describe(describe) => processor.on_describe(socket, describe).await,
+        PgWireFrontendMessage::Close(close) => processor.on_close(socket, close).await,
+        PgWireFrontendMessage::Sync(sync) => processor.on_sync(socket, sync).await,
+        PgWireFrontendMessage::Flush(_) => Ok(socket.flush().await?),
+        _ => Ok(()),
+    }
+}
+
+/// A message after which suspended portal rows are no longer needed.
+enum CursorEnd {
+    Close(String),
+    Sync,
+}
+
+fn cursor_end(message: &PgWireFrontendMessage) -> Option<CursorEnd> {
+    match message {
+        PgWireFrontendMessage::Close(close) if *close.target_type() == TARGET_TYPE_BYTE_PORTAL => {
+            let name = close.name().as_deref().unwrap_or(DEFAULT_NAME).to_owned();
+            Some(CursorEnd::Close(name))
+        }
+        PgWireFrontendMessage::Sync(_) => Some(CursorEnd::Sync),
+        _ => None,
+    }
+}
+
+/// Drops the rows of a closed portal, or at a Sync outside a transaction
+/// block, those of every suspended portal.
+fn close_cursors<S>(socket: &PgWireSocket<S>, processor: &GitQLBackend, end: Option<CursorEnd>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    match end {
+        Some(CursorEnd::Close(name)) => processor.close_cursor(&name),
+        Some(CursorEnd::Sync) if matches!(socket.transaction_status(), TransactionStatus::Idle) => {
+            processor.close_cursors()
+        }
+        _ => {}
+    }
+}
+
+/// Removes the prepared statements and portals that DEALLOCATE, CLOSE or
+/// DISCARD ALL, or the Sync ending a suspended portal, released from the
+/// client's stores.
+fn release_client_objects<S>(
+    socket: &PgWireSocket<S>,
+    processor: &GitQLBackend,
+    objects: &mut ClientObjects,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let store = socket.portal_store();
+    for release in processor.take_client_releases() {
+        match release {
+            ClientRelease::Statement(Some(name)) => {
+                store.rm_statement(&name);
+                objects.statements.remove(&name);
+            }
+            ClientRelease::Statement(None) => {
+                for name in objects.statements.drain() {
+                    store.rm_statement(&name);
+                }
+            }
+            ClientRelease::Portal(Some(name)) => {
+                store.rm_portal(&name);
+                objects.portals.remove(&name);
+            }
+            ClientRelease::Portal(None) => {
+                for name in objects.portals.drain() {
+                    store.rm_portal(&name);
+                }
+            }
+        }
+    }
+}
+
+/// Sends a ParameterStatus for every reported setting a session command
+/// changed, as PostgreSQL does for its GUC_REPORT parameters. Returns `false`
+/// when the connection must be closed.
+async fn report_parameters<S>(socket: &mut PgWireSocket<S>, processor: &GitQLBackend) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let reports = processor.take_parameter_reports();
+    if reports.is_empty() {
+        return true;
+    }
+
+    for (name, value) in reports {
+        let status = ParameterStatus::new(name, value);
+        if socket
+            .feed(PgWireBackendMessage::ParameterStatus(status))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+    }
+    socket.flush().await.is_ok()
+}
+
+fn is_extended_query(message: &PgWireFrontendMessage) -> bool {
+    matches!(
+        message,
+        PgWireFrontendMessage::Parse(_)
+            | PgWireFrontendMessage::Bind(_)
+            | PgWireFrontendMessage::Execute(_)
+            | PgWireFrontendMessage::Describe(_)
+            | PgWireFrontendMessage::Close(_)
+            | PgWireFrontendMessage::Flush(_)
+    )
+}
+
+/// Sends an error the way PostgreSQL does: a failed extended query skips
+/// messages up to the next Sync, a failed simple query is followed by
+/// ReadyForQuery. Returns `false` when the connection must be closed.
+async fn report_error<S>(
+    socket: &mut PgWireSocket<S>,
+    err: PgWireError,
+    is_extended_query: bool,
+) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let error_info = ErrorInfo::from(err);
+    let is_fatal = error_info.severity == "FATAL"
+        || matches!(
+            socket.state(),
+            PgWireConnectionState::AwaitingStartup
+                | PgWireConnectionState::AuthenticationInProgress
+        );
+
+    if socket
+        .feed(PgWireBackendMessage::ErrorResponse(error_info.into()))
+        .await
+        .is_err()
+    {
+        return false;
+    }
+
+    if is_fatal {
+        let _ = socket.close().await;
+        return false;
+    }
+
+    if is_extended_query {
+        socket.set_state(PgWireConnectionState::AwaitingSync);
+    } else {
+        let ready = ReadyForQuery::new(socket.transaction_status());
+        if socket
+            .feed(PgWireBackendMessage::ReadyForQuery(ready))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+    }
+
+    socket.flush().await.is_ok()
+}
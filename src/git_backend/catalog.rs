@@ -0,0 +1,1287 @@
+use std::cmp::Ordering;
+
+use gitql_ast::types::DataType;
+use gitql_ast::value::Value;
+use glob::Pattern;
+use pgwire::api::Type;
+
+use super::git_schema::{TABLES_FIELDS_NAMES, TABLES_FIELDS_TYPES};
+use super::result_set::ResultSet;
+use super::statements::split_tokens;
+
+const OWNER_OID: i64 = 10;
+const PG_CATALOG_NAMESPACE_OID: i64 = 11;
+const PUBLIC_NAMESPACE_OID: i64 = 2200;
+const INFORMATION_SCHEMA_NAMESPACE_OID: i64 = 13000;
+const DATABASE_OID: i64 = 16383;
+const FIRST_TABLE_OID: i64 = 16384;
+
+const CATALOG_RELATIONS: &[&str] = &[
+    "pg_class",
+    "pg_attribute",
+    "pg_type",
+    "pg_namespace",
+    "pg_database",
+    "pg_settings",
+];
+
+/// Catalog relations that have no rows here, as GitQL tables have no
+/// defaults, constraints, indexes, triggers, rules, policies, extended
+/// statistics, inheritance or publications. psql reads them when it
+/// describes a table.
+const EMPTY_CATALOG_RELATIONS: &[&str] = &[
+    "pg_attrdef",
+    "pg_constraint",
+    "pg_description",
+    "pg_index",
+    "pg_inherits",
+    "pg_policy",
+    "pg_publication",
+    "pg_publication_namespace",
+    "pg_publication_rel",
+    "pg_rewrite",
+    "pg_statistic_ext",
+    "pg_trigger",
+];
+
+/// Session values reported by the catalog relations.
+pub struct CatalogSession {
+    pub database: String,
+    pub user: String,
+    pub settings: Vec<(String, String, &'static str)>,
+}
+
+/// Answers a query that reads the PostgreSQL system catalog or the information
+/// schema, returning `None` when the query references neither and an error
+/// when it cannot be answered.
+pub fn catalog_query(query: &str, session: &CatalogSession) -> Option<Result<ResultSet, String>> {
+    let query = query.trim().trim_end_matches(';');
+    let tokens = split_tokens(query);
+    if !tokens.iter().any(|token| is_catalog_name(token)) {
+        return None;
+    }
+
+    if let Some(result) = psql_query(query, session).or_else(|| select_relation(&tokens, session)) {
+        return Some(Ok(result));
+    }
+
+    if tokens.iter().any(|token| is_empty_relation(token)) {
+        return Some(Ok(ResultSet::default()));
+    }
+
+    Some(Err("this catalog query is not supported".to_owned()))
+}
+
+fn is_catalog_name(token: &str) -> bool {
+    let token = token.to_ascii_lowercase();
+    token.starts_with("pg_catalog.")
+        || token.starts_with("information_schema.")
+        || CATALOG_RELATIONS.contains(&token.as_str())
+        || EMPTY_CATALOG_RELATIONS.contains(&token.as_str())
+}
+
+fn is_empty_relation(token: &str) -> bool {
+    let token = token.to_ascii_lowercase();
+    EMPTY_CATALOG_RELATIONS.contains(&token.strip_prefix("pg_catalog.").unwrap_or(&token))
+}
+
+fn relation(name: &str, session: &CatalogSession) -> Option<ResultSet> {
+    let name = name.to_ascii_lowercase();
+    if let Some(view) = name.strip_prefix("information_schema.") {
+        return match view {
+            "schemata" => Some(information_schema_schemata(session)),
+            "tables" => Some(information_schema_tables(session)),
+            "columns" => Some(information_schema_columns(session)),
+            _ => None,
+        };
+    }
+
+    match name.strip_prefix("pg_catalog.").unwrap_or(&name) {
+        "pg_class" => Some(pg_class()),
+        "pg_attribute" => Some(pg_attribute()),
+        "pg_type" => Some(pg_type()),
+        "pg_namespace" => Some(pg_namespace()),
+        "pg_database" => Some(pg_database(session)),
+        "pg_settings" => Some(pg_settings(session)),
+        _ => None,
+    }
+}
+
+/// GitQL tables ordered by name with the oid each one is reported under.
+pub fn tables() -> Vec<(i64, &'static str, &'static [&'static str])> {
+    let mut tables = TABLES_FIELDS_NAMES
+        .iter()
+        .map(|(name, fields)| (*name, fields.as_slice()))
+        .collect::<Vec<_>>();
+    tables.sort_by_key(|(name, _)| *name);
+
+    tables
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, fields))| (FIRST_TABLE_OID + index as i64, name, fields))
+        .collect()
+}
+
+pub fn column_type(column: &str) -> Type {
+    match TABLES_FIELDS_TYPES.get(column) {
+        Some(DataType::Integer) => Type::INT8,
+        Some(DataType::Float) => Type::FLOAT8,
+        Some(DataType::Boolean) => Type::BOOL,
+        Some(DataType::Date) => Type::DATE,
+        Some(DataType::Time) => Type::TIME,
+        Some(DataType::DateTime) => Type::TIMESTAMP,
+        _ => Type::TEXT,
+    }
+}
+
+/// Returns the SQL spelling of a type, as printed by `format_type`.
+pub fn sql_type_name(column_type: &Type) -> &'static str {
+    match *column_type {
+        Type::BOOL => "boolean",
+        Type::INT2 => "smallint",
+        Type::INT4 => "integer",
+        Type::INT8 => "bigint",
+        Type::FLOAT4 => "real",
+        Type::FLOAT8 => "double precision",
+        Type::DATE => "date",
+        Type::TIME => "time without time zone",
+        Type::TIMESTAMP => "timestamp without time zone",
+        Type::TIMESTAMPTZ => "timestamp with time zone",
+        _ => "text",
+    }
+}
+
+fn pg_types() -> Vec<(Type, i64, &'static str)> {
+    vec![
+        (Type::BOOL, 1, "B"),
+        (Type::CHAR, 1, "S"),
+        (Type::NAME, 64, "S"),
+        (Type::INT8, 8, "N"),
+        (Type::INT2, 2, "N"),
+        (Type::INT4, 4, "N"),
+        (Type::TEXT, -1, "S"),
+        (Type::OID, 4, "N"),
+        (Type::FLOAT4, 4, "N"),
+        (Type::FLOAT8, 8, "N"),
+        (Type::BPCHAR, -1, "S"),
+        (Type::VARCHAR, -1, "S"),
+        (Type::DATE, 4, "D"),
+        (Type::TIME, 8, "D"),
+        (Type::TIMESTAMP, 8, "D"),
+        (Type::TIMESTAMPTZ, 8, "D"),
+        (Type::NUMERIC, -1, "N"),
+    ]
+}
+
+fn type_length(column_type: &Type) -> i64 {
+    pg_types()
+        .into_iter()
+        .find(|(known, _, _)| known == column_type)
+        .map_or(-1, |(_, length, _)| length)
+}
+
+fn pg_class() -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("oid", Type::INT8),
+        ("relname", Type::TEXT),
+        ("relnamespace", Type::INT8),
+        ("reltype", Type::INT8),
+        ("reloftype", Type::INT8),
+        ("relowner", Type::INT8),
+        ("relam", Type::INT8),
+        ("relfilenode", Type::INT8),
+        ("reltablespace", Type::INT8),
+        ("relpages", Type::INT8),
+        ("reltuples", Type::FLOAT8),
+        ("relallvisible", Type::INT8),
+        ("reltoastrelid", Type::INT8),
+        ("relhasindex", Type::BOOL),
+        ("relisshared", Type::BOOL),
+        ("relpersistence", Type::TEXT),
+        ("relkind", Type::TEXT),
+        ("relnatts", Type::INT8),
+        ("relchecks", Type::INT8),
+        ("relhasrules", Type::BOOL),
+        ("relhastriggers", Type::BOOL),
+        ("relhassubclass", Type::BOOL),
+        ("relrowsecurity", Type::BOOL),
+        ("relforcerowsecurity", Type::BOOL),
+        ("relispopulated", Type::BOOL),
+        ("relreplident", Type::TEXT),
+        ("relispartition", Type::BOOL),
+        ("relacl", Type::TEXT),
+        ("reloptions", Type::TEXT),
+    ]);
+
+    for (oid, name, fields) in tables() {
+        result.rows.push(vec![
+            Value::Integer(oid),
+            Value::Text(name.to_owned()),
+            Value::Integer(PUBLIC_NAMESPACE_OID),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Integer(OWNER_OID),
+            Value::Integer(2),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Float(-1.0),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Text("p".to_owned()),
+            Value::Text("r".to_owned()),
+            Value::Integer(fields.len() as i64),
+            Value::Integer(0),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(true),
+            Value::Text("d".to_owned()),
+            Value::Boolean(false),
+            Value::Null,
+            Value::Null,
+        ]);
+    }
+
+    result
+}
+
+fn pg_attribute() -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("attrelid", Type::INT8),
+        ("attname", Type::TEXT),
+        ("atttypid", Type::INT8),
+        ("attstattarget", Type::INT8),
+        ("attlen", Type::INT8),
+        ("attnum", Type::INT8),
+        ("attndims", Type::INT8),
+        ("atttypmod", Type::INT8),
+        ("attbyval", Type::BOOL),
+        ("attstorage", Type::TEXT),
+        ("attnotnull", Type::BOOL),
+        ("atthasdef", Type::BOOL),
+        ("attidentity", Type::TEXT),
+        ("attgenerated", Type::TEXT),
+        ("attisdropped", Type::BOOL),
+        ("attislocal", Type::BOOL),
+        ("attinhcount", Type::INT8),
+        ("attcollation", Type::INT8),
+    ]);
+
+    for (oid, _, fields) in tables() {
+        for (index, field) in fields.iter().enumerate() {
+            let field_type = column_type(field);
+            let length = type_length(&field_type);
+            let is_text = field_type == Type::TEXT;
+            result.rows.push(vec![
+                Value::Integer(oid),
+                Value::Text(field.to_string()),
+                Value::Integer(i64::from(field_type.oid())),
+                Value::Integer(-1),
+                Value::Integer(length),
+                Value::Integer(index as i64 + 1),
+                Value::Integer(0),
+                Value::Integer(-1),
+                Value::Boolean(length > 0),
+                Value::Text(if is_text { "x" } else { "p" }.to_owned()),
+                Value::Boolean(false),
+                Value::Boolean(false),
+                Value::Text(String::new()),
+                Value::Text(String::new()),
+                Value::Boolean(false),
+                Value::Boolean(true),
+                Value::Integer(0),
+                Value::Integer(if is_text { 100 } else { 0 }),
+            ]);
+        }
+    }
+
+    result
+}
+
+fn pg_type() -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("oid", Type::INT8),
+        ("typname", Type::TEXT),
+        ("typnamespace", Type::INT8),
+        ("typowner", Type::INT8),
+        ("typlen", Type::INT8),
+        ("typbyval", Type::BOOL),
+        ("typtype", Type::TEXT),
+        ("typcategory", Type::TEXT),
+        ("typisdefined", Type::BOOL),
+        ("typdelim", Type::TEXT),
+        ("typrelid", Type::INT8),
+        ("typelem", Type::INT8),
+        ("typarray", Type::INT8),
+        ("typbasetype", Type::INT8),
+        ("typtypmod", Type::INT8),
+        ("typnotnull", Type::BOOL),
+        ("typcollation", Type::INT8),
+    ]);
+
+    for (pg_type, length, category) in pg_types() {
+        result.rows.push(vec![
+            Value::Integer(i64::from(pg_type.oid())),
+            Value::Text(pg_type.name().to_owned()),
+            Value::Integer(PG_CATALOG_NAMESPACE_OID),
+            Value::Integer(OWNER_OID),
+            Value::Integer(length),
+            Value::Boolean(length > 0),
+            Value::Text("b".to_owned()),
+            Value::Text(category.to_owned()),
+            Value::Boolean(true),
+            Value::Text(",".to_owned()),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Integer(0),
+            Value::Integer(-1),
+            Value::Boolean(false),
+            Value::Integer(if category == "S" { 100 } else { 0 }),
+        ]);
+    }
+
+    result
+}
+
+fn pg_namespace() -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("oid", Type::INT8),
+        ("nspname", Type::TEXT),
+        ("nspowner", Type::INT8),
+        ("nspacl", Type::TEXT),
+    ]);
+
+    for (oid, name) in [
+        (PG_CATALOG_NAMESPACE_OID, "pg_catalog"),
+        (PUBLIC_NAMESPACE_OID, "public"),
+        (INFORMATION_SCHEMA_NAMESPACE_OID, "information_schema"),
+    ] {
+        result.rows.push(vec![
+            Value::Integer(oid),
+            Value::Text(name.to_owned()),
+            Value::Integer(OWNER_OID),
+            Value::Null,
+        ]);
+    }
+
+    result
+}
+
+fn pg_database(session: &CatalogSession) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("oid", Type::INT8),
+        ("datname", Type::TEXT),
+        ("datdba", Type::INT8),
+        ("encoding", Type::INT8),
+        ("datcollate", Type::TEXT),
+        ("datctype", Type::TEXT),
+        ("datistemplate", Type::BOOL),
+        ("datallowconn", Type::BOOL),
+        ("datconnlimit", Type::INT8),
+        ("datacl", Type::TEXT),
+    ]);
+
+    result.rows.push(vec![
+        Value::Integer(DATABASE_OID),
+        Value::Text(session.database.clone()),
+        Value::Integer(OWNER_OID),
+        Value::Integer(6),
+        Value::Text("C".to_owned()),
+        Value::Text("C".to_owned()),
+        Value::Boolean(false),
+        Value::Boolean(true),
+        Value::Integer(-1),
+        Value::Null,
+    ]);
+
+    result
+}
+
+fn pg_settings(session: &CatalogSession) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("name", Type::TEXT),
+        ("setting", Type::TEXT),
+        ("unit", Type::TEXT),
+        ("category", Type::TEXT),
+        ("short_desc", Type::TEXT),
+        ("extra_desc", Type::TEXT),
+        ("context", Type::TEXT),
+        ("vartype", Type::TEXT),
+        ("source", Type::TEXT),
+        ("boot_val", Type::TEXT),
+        ("reset_val", Type::TEXT),
+    ]);
+
+    for (name, setting, description) in &session.settings {
+        result.rows.push(vec![
+            Value::Text(name.clone()),
+            Value::Text(setting.clone()),
+            Value::Null,
+            Value::Text(String::new()),
+            Value::Text(description.to_string()),
+            Value::Null,
+            Value::Text("user".to_owned()),
+            Value::Text("string".to_owned()),
+            Value::Text("default".to_owned()),
+            Value::Text(setting.clone()),
+            Value::Text(setting.clone()),
+        ]);
+    }
+
+    result
+}
+
+fn information_schema_schemata(session: &CatalogSession) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("catalog_name", Type::TEXT),
+        ("schema_name", Type::TEXT),
+        ("schema_owner", Type::TEXT),
+        ("default_character_set_catalog", Type::TEXT),
+        ("default_character_set_schema", Type::TEXT),
+        ("default_character_set_name", Type::TEXT),
+        ("sql_path", Type::TEXT),
+    ]);
+
+    for schema in ["pg_catalog", "public", "information_schema"] {
+        result.rows.push(vec![
+            Value::Text(session.database.clone()),
+            Value::Text(schema.to_owned()),
+            Value::Text(session.user.clone()),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+        ]);
+    }
+
+    result
+}
+
+fn information_schema_tables(session: &CatalogSession) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("table_catalog", Type::TEXT),
+        ("table_schema", Type::TEXT),
+        ("table_name", Type::TEXT),
+        ("table_type", Type::TEXT),
+        ("self_referencing_column_name", Type::TEXT),
+        ("reference_generation", Type::TEXT),
+        ("user_defined_type_catalog", Type::TEXT),
+        ("user_defined_type_schema", Type::TEXT),
+        ("user_defined_type_name", Type::TEXT),
+        ("is_insertable_into", Type::TEXT),
+        ("is_typed", Type::TEXT),
+        ("commit_action", Type::TEXT),
+    ]);
+
+    for (_, name, _) in tables() {
+        result.rows.push(vec![
+            Value::Text(session.database.clone()),
+            Value::Text("public".to_owned()),
+            Value::Text(name.to_owned()),
+            Value::Text("BASE TABLE".to_owned()),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Text("NO".to_owned()),
+            Value::Text("NO".to_owned()),
+            Value::Null,
+        ]);
+    }
+
+    result
+}
+
+fn information_schema_columns(session: &CatalogSession) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("table_catalog", Type::TEXT),
+        ("table_schema", Type::TEXT),
+        ("table_name", Type::TEXT),
+        ("column_name", Type::TEXT),
+        ("ordinal_position", Type::INT8),
+        ("column_default", Type::TEXT),
+        ("is_nullable", Type::TEXT),
+        ("data_type", Type::TEXT),
+        ("character_maximum_length", Type::INT8),
+        ("numeric_precision", Type::INT8),
+        ("numeric_precision_radix", Type::INT8),
+        ("numeric_scale", Type::INT8),
+        ("datetime_precision", Type::INT8),
+        ("udt_catalog", Type::TEXT),
+        ("udt_schema", Type::TEXT),
+        ("udt_name", Type::TEXT),
+        ("is_updatable", Type::TEXT),
+    ]);
+
+    for (_, table, fields) in tables() {
+        for (index, field) in fields.iter().enumerate() {
+            let field_type = column_type(field);
+            let (precision, radix, scale) = if field_type == Type::INT8 {
+                (Value::Integer(64), Value::Integer(2), Value::Integer(0))
+            } else if field_type == Type::FLOAT8 {
+                (Value::Integer(53), Value::Integer(2), Value::Null)
+            } else {
+                (Value::Null, Value::Null, Value::Null)
+            };
+            let datetime_precision = if field_type == Type::DATE {
+                Value::Integer(0)
+            } else if field_type == Type::TIME || field_type == Type::TIMESTAMP {
+                Value::Integer(6)
+            } else {
+                Value::Null
+            };
+
+            result.rows.push(vec![
+                Value::Text(session.database.clone()),
+                Value::Text("public".to_owned()),
+                Value::Text(table.to_owned()),
+                Value::Text(field.to_string()),
+                Value::Integer(index as i64 + 1),
+                Value::Null,
+                Value::Text("YES".to_owned()),
+                Value::Text(sql_type_name(&field_type).to_owned()),
+                Value::Null,
+                precision,
+                radix,
+                scale,
+                datetime_precision,
+                Value::Text(session.database.clone()),
+                Value::Text("pg_catalog".to_owned()),
+                Value::Text(field_type.name().to_owned()),
+                Value::Text("NO".to_owned()),
+            ]);
+        }
+    }
+
+    result
+}
+
+/// Recognizes the catalog queries psql sends for `\dt` and `\d name`, which
+/// use joins and functions beyond what `select_relation` understands.
+fn psql_query(query: &str, session: &CatalogSession) -> Option<ResultSet> {
+    let lowercase = query.to_ascii_lowercase();
+
+    if lowercase.contains("from pg_catalog.pg_class c") && lowercase.contains("as \"name\"") {
+        return Some(psql_list_relations(query, &lowercase, session));
+    }
+
+    if lowercase.starts_with("select c.oid,")
+        && lowercase.contains("c.relname operator(pg_catalog.~)")
+    {
+        let pattern = relname_pattern(query, &lowercase);
+        let mut result = ResultSet::new(&[
+            ("oid", Type::INT8),
+            ("nspname", Type::TEXT),
+            ("relname", Type::TEXT),
+        ]);
+        for (oid, name, _) in tables() {
+            if pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.matches(name))
+            {
+                result.rows.push(vec![
+                    Value::Integer(oid),
+                    Value::Text("public".to_owned()),
+                    Value::Text(name.to_owned()),
+                ]);
+            }
+        }
+        return Some(result);
+    }
+
+    if lowercase.contains("c.relchecks") {
+        let oid = quoted_oid(&lowercase, "c.oid = '")?;
+        return Some(psql_table_info(oid));
+    }
+
+    if lowercase.contains("from pg_catalog.pg_attribute a") {
+        let oid = quoted_oid(&lowercase, "a.attrelid = '")?;
+        return Some(psql_table_columns(oid, &lowercase));
+    }
+
+    None
+}
+
+fn psql_list_relations(query: &str, lowercase: &str, session: &CatalogSession) -> ResultSet {
+    let mut columns = vec![
+        ("Schema", Type::TEXT),
+        ("Name", Type::TEXT),
+        ("Type", Type::TEXT),
+        ("Owner", Type::TEXT),
+    ];
+    let extra_columns = [
+        ("Persistence", Value::Text("permanent".to_owned())),
+        ("Access method", Value::Text("heap".to_owned())),
+        ("Size", Value::Text("0 bytes".to_owned())),
+        ("Description", Value::Null),
+    ]
+    .into_iter()
+    .filter(|(name, _)| lowercase.contains(&format!("as \"{}\"", name.to_lowercase())))
+    .collect::<Vec<_>>();
+    columns.extend(extra_columns.iter().map(|(name, _)| (*name, Type::TEXT)));
+
+    let mut result = ResultSet::new(&columns);
+    let lists_tables = lowercase
+        .split("c.relkind in (")
+        .nth(1)
+        .and_then(|kinds| kinds.split(')').next())
+        .map_or(true, |kinds| kinds.contains("'r'"));
+    if !lists_tables {
+        return result;
+    }
+
+    let pattern = relname_pattern(query, lowercase);
+    for (_, name, _) in tables() {
+        if pattern
+            .as_ref()
+            .map_or(false, |pattern| !pattern.matches(name))
+        {
+            continue;
+        }
+
+        let mut row = vec![
+            Value::Text("public".to_owned()),
+            Value::Text(name.to_owned()),
+            Value::Text("table".to_owned()),
+            Value::Text(session.user.clone()),
+        ];
+        row.extend(extra_columns.iter().map(|(_, value)| value.clone()));
+        result.rows.push(row);
+    }
+
+    result
+}
+
+fn psql_table_info(oid: i64) -> ResultSet {
+    let mut result = ResultSet::new(&[
+        ("relchecks", Type::INT8),
+        ("relkind", Type::TEXT),
+        ("relhasindex", Type::BOOL),
+        ("relhasrules", Type::BOOL),
+        ("relhastriggers", Type::BOOL),
+        ("relrowsecurity", Type::BOOL),
+        ("relforcerowsecurity", Type::BOOL),
+        ("relhasoids", Type::BOOL),
+        ("relispartition", Type::BOOL),
+        ("reloptions", Type::TEXT),
+        ("reltablespace", Type::INT8),
+        ("reloftype", Type::TEXT),
+        ("relpersistence", Type::TEXT),
+        ("relreplident", Type::TEXT),
+        ("amname", Type::TEXT),
+    ]);
+
+    if tables().iter().any(|(table_oid, _, _)| *table_oid == oid) {
+        result.rows.push(vec![
+            Value::Integer(0),
+            Value::Text("r".to_owned()),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Boolean(false),
+            Value::Text(String::new()),
+            Value::Integer(0),
+            Value::Text(String::new()),
+            Value::Text("p".to_owned()),
+            Value::Text("d".to_owned()),
+            Value::Text("heap".to_owned()),
+        ]);
+    }
+
+    result
+}
+
+fn psql_table_columns(oid: i64, lowercase: &str) -> ResultSet {
+    let mut columns = vec![
+        ("attname", Type::TEXT),
+        ("format_type", Type::TEXT),
+        ("attdefault", Type::TEXT),
+        ("attnotnull", Type::BOOL),
+        ("attcollation", Type::TEXT),
+        ("attidentity", Type::TEXT),
+        ("attgenerated", Type::TEXT),
+    ];
+    let with_storage = lowercase.contains("a.attstorage");
+    let with_compression = lowercase.contains("attcompression");
+    let with_statistics = lowercase.contains("attstattarget");
+    let with_description = lowercase.contains("col_description");
+    for (name, included) in [
+        ("attstorage", with_storage),
+        ("attcompression", with_compression),
+        ("attstattarget", with_statistics),
+        ("description", with_description),
+    ] {
+        if included {
+            columns.push((name, Type::TEXT));
+        }
+    }
+
+    let mut result = ResultSet::new(&columns);
+    let fields = tables()
+        .into_iter()
+        .find(|(table_oid, _, _)| *table_oid == oid)
+        .map_or(&[][..], |(_, _, fields)| fields);
+
+    for field in fields {
+        let field_type = column_type(field);
+        let mut row = vec![
+            Value::Text(field.to_string()),
+            Value::Text(sql_type_name(&field_type).to_owned()),
+            Value::Null,
+            Value::Boolean(false),
+            Value::Null,
+            Value::Text(String::new()),
+            Value::Text(String::new()),
+        ];
+        if with_storage {
+            let storage = if field_type == Type::TEXT { "x" } else { "p" };
+            row.push(Value::Text(storage.to_owned()));
+        }
+        if with_compression {
+            row.push(Value::Text(String::new()));
+        }
+        if with_statistics {
+            row.push(Value::Null);
+        }
+        if with_description {
+            row.push(Value::Null);
+        }
+        result.rows.push(row);
+    }
+
+    result
+}
+
+/// Turns the `^(name)$` regular expression psql builds from a `\d` pattern
+/// back into a glob.
+fn relname_pattern(query: &str, lowercase: &str) -> Option<Pattern> {
+    let marker = "c.relname operator(pg_catalog.~) '";
+    let start = lowercase.find(marker)? + marker.len();
+    let end = start + lowercase[start..].find('\'')?;
+    let regex = &query[start..end];
+    let regex = regex.strip_prefix("^(").unwrap_or(regex);
+    let regex = regex.strip_suffix(")$").unwrap_or(regex);
+
+    let mut glob = String::new();
+    let mut chars = regex.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if chars.peek() == Some(&'*') => {
+                chars.next();
+                glob.push('*');
+            }
+            '.' => glob.push('?'),
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    glob.push_str(&Pattern::escape(&escaped.to_string()));
+                }
+            }
+            _ => glob.push_str(&Pattern::escape(&c.to_string())),
+        }
+    }
+
+    Pattern::new(&glob).ok()
+}
+
+fn quoted_oid(lowercase: &str, marker: &str) -> Option<i64> {
+    let start = lowercase.find(marker)? + marker.len();
+    let end = start + lowercase[start..].find('\'')?;
+    lowercase[start..end].parse::<i64>().ok()
+}
+
+enum SelectItem {
+    All,
+    Column(String, String),
+}
+
+enum Condition {
+    Compare(String, String, Value),
+    In(String, Vec<Value>),
+    IsNull(String, bool),
+}
+
+/// Evaluates `SELECT columns FROM relation [alias] [WHERE ...] [ORDER BY ...]
+/// [LIMIT n]` against one catalog relation, where the WHERE clause is a list
+/// of comparisons with literals joined by AND.
+fn select_relation(tokens: &[String], session: &CatalogSession) -> Option<ResultSet> {
+    let mut tokens = TokenCursor {
+        tokens,
+        position: 0,
+    };
+
+    tokens.expect("select")?;
+    let mut items = vec![];
+    loop {
+        items.push(select_item(&mut tokens)?);
+        if !tokens.accept(",") {
+            break;
+        }
+    }
+
+    tokens.expect("from")?;
+    let relation = relation(tokens.next()?, session)?;
+    if tokens.accept("as") {
+        tokens.next()?;
+    } else if tokens.peek().map_or(false, |token| {
+        !["where", "order", "limit"].contains(&token.to_ascii_lowercase().as_str())
+    }) {
+        tokens.next();
+    }
+
+    let mut conditions = vec![];
+    if tokens.accept("where") {
+        loop {
+            conditions.push(condition(&mut tokens)?);
+            if !tokens.accept("and") {
+                break;
+            }
+        }
+    }
+
+    let mut order = vec![];
+    if tokens.accept("order") {
+        tokens.expect("by")?;
+        loop {
+            let column = column_name(tokens.next()?);
+            let descending = tokens.accept("desc");
+            if !descending {
+                tokens.accept("asc");
+            }
+            order.push((column, descending));
+            if !tokens.accept(",") {
+                break;
+            }
+        }
+    }
+
+    let mut limit = None;
+    if tokens.accept("limit") {
+        limit = Some(tokens.next()?.parse::<usize>().ok()?);
+    }
+
+    if tokens.peek().is_some() {
+        return None;
+    }
+
+    project(relation, &items, &conditions, &order, limit)
+}
+
+fn project(
+    relation: ResultSet,
+    items: &[SelectItem],
+    conditions: &[Condition],
+    order: &[(String, bool)],
+    limit: Option<usize>,
+) -> Option<ResultSet> {
+    let index_of = |name: &str| {
+        relation
+            .columns
+            .iter()
+            .position(|(column, _)| column.eq_ignore_ascii_case(name))
+    };
+
+    let mut projection = vec![];
+    for item in items {
+        match item {
+            SelectItem::All => projection.extend(
+                relation
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| (index, name.clone())),
+            ),
+            SelectItem::Column(name, alias) => projection.push((index_of(name)?, alias.clone())),
+        }
+    }
+
+    let mut filters = vec![];
+    for condition in conditions {
+        let column = match condition {
+            Condition::Compare(column, _, _)
+            | Condition::In(column, _)
+            | Condition::IsNull(column, _) => column,
+        };
+        filters.push((index_of(column)?, condition));
+    }
+
+    let mut sort_keys = vec![];
+    for (column, descending) in order {
+        sort_keys.push((index_of(column)?, *descending));
+    }
+
+    let mut rows = relation
+        .rows
+        .iter()
+        .filter(|row| {
+            filters
+                .iter()
+                .all(|(index, condition)| matches_condition(&row[*index], condition))
+        })
+        .collect::<Vec<_>>();
+
+    rows.sort_by(|left, right| {
+        sort_keys
+            .iter()
+            .map(|(index, descending)| {
+                let ordering =
+                    compare_values(&left[*index], &right[*index]).unwrap_or(Ordering::Equal);
+                if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let mut result = ResultSet {
+        columns: projection
+            .iter()
+            .map(|(index, name)| (name.clone(), relation.columns[*index].1.clone()))
+            .collect(),
+        rows: vec![],
+    };
+    result.rows = rows
+        .into_iter()
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|row| {
+            projection
+                .iter()
+                .map(|(index, _)| row[*index].clone())
+                .collect()
+        })
+        .collect();
+
+    Some(result)
+}
+
+fn select_item(tokens: &mut TokenCursor) -> Option<SelectItem> {
+    let token = tokens.next()?;
+    if token == "*" {
+        return Some(SelectItem::All);
+    }
+
+    if token.ends_with('.') && tokens.accept("*") {
+        return Some(SelectItem::All);
+    }
+
+    if !is_identifier(token) {
+        return None;
+    }
+
+    let name = column_name(token);
+    let alias = if tokens.accept("as") {
+        column_name(tokens.next()?)
+    } else if tokens.peek().map_or(false, |next| {
+        is_identifier(next) && !next.eq_ignore_ascii_case("from")
+    }) {
+        column_name(tokens.next()?)
+    } else {
+        name.clone()
+    };
+
+    Some(SelectItem::Column(name, alias))
+}
+
+fn condition(tokens: &mut TokenCursor) -> Option<Condition> {
+    let column = column_name(tokens.next()?);
+    let operator = tokens.next()?.to_ascii_lowercase();
+
+    match operator.as_str() {
+        "=" | "<>" | "!=" | "<" | ">" | "<=" | ">=" => {
+            Some(Condition::Compare(column, operator, literal(tokens)?))
+        }
+        "in" => {
+            tokens.expect("(")?;
+            let mut values = vec![literal(tokens)?];
+            while tokens.accept(",") {
+                values.push(literal(tokens)?);
+            }
+            tokens.expect(")")?;
+            Some(Condition::In(column, values))
+        }
+        "is" => {
+            let negated = tokens.accept("not");
+            tokens.expect("null")?;
+            Some(Condition::IsNull(column, negated))
+        }
+        _ => None,
+    }
+}
+
+fn literal(tokens: &mut TokenCursor) -> Option<Value> {
+    let mut token = tokens.next()?.to_owned();
+    if token == "-" {
+        token = format!("-{}", tokens.next()?);
+    }
+
+    if token.len() >= 2 && (token.starts_with('\'') || token.starts_with('"')) {
+        return Some(Value::Text(token[1..token.len() - 1].to_owned()));
+    }
+
+    match token.to_ascii_lowercase().as_str() {
+        "true" => return Some(Value::Boolean(true)),
+        "false" => return Some(Value::Boolean(false)),
+        "null" => return Some(Value::Null),
+        _ => {}
+    }
+
+    if let Ok(integer) = token.parse::<i64>() {
+        return Some(Value::Integer(integer));
+    }
+
+    token.parse::<f64>().ok().map(Value::Float)
+}
+
+fn matches_condition(value: &Value, condition: &Condition) -> bool {
+    match condition {
+        Condition::Compare(_, operator, literal) => {
+            let ordering = match compare_values(value, literal) {
+                Some(ordering) => ordering,
+                None => return false,
+            };
+            match operator.as_str() {
+                "=" => ordering == Ordering::Equal,
+                "<>" | "!=" => ordering != Ordering::Equal,
+                "<" => ordering == Ordering::Less,
+                ">" => ordering == Ordering::Greater,
+                "<=" => ordering != Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }
+        }
+        Condition::In(_, literals) => literals
+            .iter()
+            .any(|literal| compare_values(value, literal) == Some(Ordering::Equal)),
+        Condition::IsNull(_, negated) => matches!(value, Value::Null) != *negated,
+    }
+}
+
+fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Integer(left), Value::Integer(right)) => Some(left.cmp(right)),
+        (Value::Integer(left), Value::Float(right)) => (*left as f64).partial_cmp(right),
+        (Value::Float(left), Value::Integer(right)) => left.partial_cmp(&(*right as f64)),
+        (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+        (Value::Boolean(left), Value::Boolean(right)) => Some(left.cmp(right)),
+        _ => Some(value_text(left).cmp(&value_text(right))),
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Text(text) => text.clone(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Float(float) => float.to_string(),
+        Value::Boolean(boolean) => boolean.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn is_identifier(token: &str) -> bool {
+    token.starts_with('"')
+        || token
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_alphabetic() || c == '_')
+}
+
+/// Resolves a possibly qualified column reference to the column name, folding
+/// unquoted names to lower case.
+fn column_name(token: &str) -> String {
+    if token.len() >= 2 && token.starts_with('"') && token.ends_with('"') {
+        return token[1..token.len() - 1].to_owned();
+    }
+
+    token
+        .rsplit('.')
+        .next()
+        .unwrap_or(token)
+        .to_ascii_lowercase()
+}
+
+struct TokenCursor<'t> {
+    tokens: &'t [String],
+    position: usize,
+}
+
+impl<'t> TokenCursor<'t> {
+    fn peek(&self) -> Option<&'t str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'t str> {
+        let token = self.peek()?;
+        self.position += 1;
+        Some(token)
+    }
+
+    fn accept(&mut self, expected: &str) -> bool {
+        if self
+            .peek()
+            .map_or(false, |token| token.eq_ignore_ascii_case(expected))
+        {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: &str) -> Option<()> {
+        self.accept(expected).then_some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Queries psql 16 sends for `\dt` and `\d commits`.
+    const PSQL_LIST_TABLES: &str = r#"SELECT n.nspname as "Schema",
+  c.relname as "Name",
+  CASE c.relkind WHEN 'r' THEN 'table' WHEN 'v' THEN 'view' WHEN 'm' THEN 'materialized view' WHEN 'i' THEN 'index' WHEN 'S' THEN 'sequence' WHEN 't' THEN 'TOAST table' WHEN 'f' THEN 'foreign table' WHEN 'p' THEN 'partitioned table' WHEN 'I' THEN 'partitioned index' END as "Type",
+  pg_catalog.pg_get_userbyid(c.relowner) as "Owner"
+FROM pg_catalog.pg_class c
+     LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+     LEFT JOIN pg_catalog.pg_am am ON am.oid = c.relam
+WHERE c.relkind IN ('r','p','')
+      AND n.nspname <> 'pg_catalog'
+      AND n.nspname !~ '^pg_toast'
+      AND n.nspname <> 'information_schema'
+  AND pg_catalog.pg_table_is_visible(c.oid)
+ORDER BY 1,2;"#;
+
+    const PSQL_FIND_TABLE: &str = r#"SELECT c.oid,
+  n.nspname,
+  c.relname
+FROM pg_catalog.pg_class c
+     LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+WHERE c.relname OPERATOR(pg_catalog.~) '^(commits)$' COLLATE pg_catalog.default
+  AND pg_catalog.pg_table_is_visible(c.oid)
+ORDER BY 2, 3;"#;
+
+    const PSQL_TABLE_INFO: &str = r#"SELECT c.relchecks, c.relkind, c.relhasindex, c.relhasrules, c.relhastriggers, c.relrowsecurity, c.relforcerowsecurity, false AS relhasoids, c.relispartition, '', c.reltablespace, CASE WHEN c.reloftype = 0 THEN '' ELSE c.reloftype::pg_catalog.regtype::pg_catalog.text END, c.relpersistence, c.relreplident, am.amname
+FROM pg_catalog.pg_class c
+ LEFT JOIN pg_catalog.pg_class tc ON (c.reltoastrelid = tc.oid)
+LEFT JOIN pg_catalog.pg_am am ON (c.relam = am.oid)
+WHERE c.oid = '{oid}';"#;
+
+    const PSQL_TABLE_COLUMNS: &str = r#"SELECT a.attname,
+  pg_catalog.format_type(a.atttypid, a.atttypmod),
+  (SELECT pg_catalog.pg_get_expr(d.adbin, d.adrelid, true)
+   FROM pg_catalog.pg_attrdef d
+   WHERE d.adrelid = a.attrelid AND d.adnum = a.attnum AND a.atthasdef),
+  a.attnotnull,
+  (SELECT c.collname FROM pg_catalog.pg_collation c, pg_catalog.pg_type t
+   WHERE c.oid = a.attcollation AND t.oid = a.atttypid AND a.attcollation <> t.typcollation) AS attcollation,
+  a.attidentity,
+  a.attgenerated
+FROM pg_catalog.pg_attribute a
+WHERE a.attrelid = '{oid}' AND a.attnum > 0 AND NOT a.attisdropped
+ORDER BY a.attnum;"#;
+
+    const PSQL_TABLE_POLICIES: &str = r#"SELECT pol.polname, pol.polpermissive,
+  CASE WHEN pol.polroles = '{0}' THEN NULL ELSE pg_catalog.array_to_string(array(select rolname from pg_catalog.pg_roles where oid = any (pol.polroles) order by 1),',') END,
+  pg_catalog.pg_get_expr(pol.polqual, pol.polrelid),
+  pg_catalog.pg_get_expr(pol.polwithcheck, pol.polrelid),
+  CASE pol.polcmd
+    WHEN 'r' THEN 'SELECT'
+    WHEN 'a' THEN 'INSERT'
+    WHEN 'w' THEN 'UPDATE'
+    WHEN 'd' THEN 'DELETE'
+    END AS cmd
+FROM pg_catalog.pg_policy pol
+WHERE pol.polrelid = '{oid}' ORDER BY 1;"#;
+
+    const PSQL_TABLE_INHERITANCE: &str = r#"SELECT c.oid::pg_catalog.regclass
+FROM pg_catalog.pg_class c, pg_catalog.pg_inherits i
+WHERE c.oid = i.inhparent AND i.inhrelid = '{oid}'
+  AND c.relkind != 'p' AND c.relkind != 'I'
+ORDER BY inhseqno;"#;
+
+    fn session() -> CatalogSession {
+        CatalogSession {
+            database: "git".to_owned(),
+            user: "git".to_owned(),
+            settings: vec![],
+        }
+    }
+
+    fn commits_oid() -> i64 {
+        tables()
+            .into_iter()
+            .find(|(_, name, _)| *name == "commits")
+            .map(|(oid, _, _)| oid)
+            .unwrap()
+    }
+
+    fn text_column(result: &ResultSet, column: usize) -> Vec<String> {
+        result
+            .rows
+            .iter()
+            .map(|row| match &row[column] {
+                Value::Text(text) => text.clone(),
+                _ => panic!("expected text in column {}", column),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lists_tables_for_psql() {
+        let result = catalog_query(PSQL_LIST_TABLES, &session())
+            .unwrap()
+            .unwrap();
+        let names = text_column(&result, 1);
+        assert_eq!(names.len(), TABLES_FIELDS_NAMES.len());
+        assert!(names.contains(&"commits".to_owned()));
+        assert!(names.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn describes_a_table_for_psql() {
+        let result = catalog_query(PSQL_FIND_TABLE, &session()).unwrap().unwrap();
+        assert_eq!(text_column(&result, 2), vec!["commits".to_owned()]);
+
+        let oid = commits_oid().to_string();
+        let info = PSQL_TABLE_INFO.replace("{oid}", &oid);
+        let result = catalog_query(&info, &session()).unwrap().unwrap();
+        assert_eq!(result.rows.len(), 1);
+
+        let columns = PSQL_TABLE_COLUMNS.replace("{oid}", &oid);
+        let result = catalog_query(&columns, &session()).unwrap().unwrap();
+        let fields = TABLES_FIELDS_NAMES.get("commits").unwrap();
+        assert_eq!(text_column(&result, 0).len(), fields.len());
+    }
+
+    #[test]
+    fn answers_empty_relations_psql_reads_with_no_rows() {
+        let oid = commits_oid().to_string();
+        for query in [PSQL_TABLE_POLICIES, PSQL_TABLE_INHERITANCE] {
+            let query = query.replace("{oid}", &oid);
+            let result = catalog_query(&query, &session()).unwrap().unwrap();
+            assert!(result.rows.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_unrecognized_catalog_queries() {
+        let query = "SELECT p.proname FROM pg_catalog.pg_proc p JOIN pg_catalog.pg_namespace n ON n.oid = p.pronamespace";
+        assert!(catalog_query(query, &session()).unwrap().is_err());
+    }
+
+    #[test]
+    fn ignores_queries_without_catalog_relations() {
+        assert!(catalog_query("SELECT name FROM commits", &session()).is_none());
+    }
+}
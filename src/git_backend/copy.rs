@@ -0,0 +1,367 @@
+use bytes::BytesMut;
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use gitql_ast::object::GitQLObject;
+use gitql_ast::value::Value;
+use pgwire::error::PgWireResult;
+use pgwire::messages::copy::CopyData;
+
+use super::git_row::value_text;
+use super::statements::split_tokens;
+
+#[derive(PartialEq)]
+pub enum CopyFormat {
+    Text,
+    Csv,
+}
+
+/// `COPY (query) TO STDOUT` with the options that control how rows are
+/// written.
+pub struct CopyQuery {
+    pub query: String,
+    pub format: CopyFormat,
+    pub header: bool,
+    pub delimiter: char,
+    pub null: String,
+}
+
+impl CopyQuery {
+    /// Encodes the header, when requested, and every row as one copy data
+    /// message per line, returning the number of rows.
+    pub fn encode(
+        &self,
+        groups: &GitQLObject,
+    ) -> (usize, BoxStream<'static, PgWireResult<CopyData>>) {
+        let rows = groups.groups.first().map_or(&[][..], |group| &group.rows);
+        let data = self
+            .header(&groups.titles)
+            .into_iter()
+            .chain(rows.iter().map(|row| self.row(&row.values)))
+            .map(Ok)
+            .collect::<Vec<_>>();
+        (rows.len(), stream::iter(data).boxed())
+    }
+
+    /// Encodes the header line of the columns `titles`, when requested.
+    pub fn header(&self, titles: &[String]) -> Option<CopyData> {
+        self.header
+            .then(|| copy_data(self.line(titles.iter().cloned().map(Some))))
+    }
+
+    /// Encodes one row as a line of copy data.
+    pub fn row<'v>(&self, values: impl IntoIterator<Item = &'v Value>) -> CopyData {
+        copy_data(self.line(values.into_iter().map(value_text)))
+    }
+
+    fn line(&self, fields: impl Iterator<Item = Option<String>>) -> String {
+        let mut line = fields
+            .map(|field| match field {
+                None => self.null.clone(),
+                Some(field) if self.format == CopyFormat::Csv => self.csv_field(&field),
+                Some(field) => self.text_field(&field),
+            })
+            .collect::<Vec<_>>()
+            .join(&self.delimiter.to_string());
+        line.push('\n');
+        line
+    }
+
+    fn csv_field(&self, field: &str) -> String {
+        let needs_quotes = field == self.null
+            || field.contains(|c| c == self.delimiter || c == '"' || c == '\n' || c == '\r');
+        if needs_quotes {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+
+    fn text_field(&self, field: &str) -> String {
+        let mut escaped = String::with_capacity(field.len());
+        for c in field.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c == self.delimiter => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
+fn copy_data(line: String) -> CopyData {
+    CopyData::new(BytesMut::from(line.as_str()))
+}
+
+/// Recognizes a COPY statement, returning `None` for anything else and an
+/// error for a form of COPY that is not supported.
+pub fn parse_copy_query(statement: &str) -> Option<Result<CopyQuery, String>> {
+    let statement = statement.trim().trim_end_matches(';').trim();
+    let keyword = statement.split_whitespace().next()?;
+    if !keyword.eq_ignore_ascii_case("copy") {
+        return None;
+    }
+
+    Some(parse_copy(statement[keyword.len()..].trim_start()))
+}
+
+fn parse_copy(rest: &str) -> Result<CopyQuery, String> {
+    let query_end = query_end(rest).ok_or_else(|| {
+        "COPY only supports a query, as in COPY (SELECT ...) TO STDOUT".to_owned()
+    })?;
+    let query = rest[1..query_end].trim().to_owned();
+    let tokens = split_tokens(&rest[query_end + 1..]);
+    let mut tokens = tokens.iter().map(String::as_str).peekable();
+
+    let target = (tokens.next(), tokens.next());
+    if !matches!(target, (Some(to), Some(stdout))
+        if to.eq_ignore_ascii_case("to") && stdout.eq_ignore_ascii_case("stdout"))
+    {
+        return Err("COPY only supports TO STDOUT".to_owned());
+    }
+
+    let mut options = vec![];
+    if tokens
+        .peek()
+        .map_or(false, |token| token.eq_ignore_ascii_case("with"))
+    {
+        tokens.next();
+    }
+    if tokens.peek() == Some(&"(") {
+        tokens.next();
+        loop {
+            let name = tokens.next().ok_or_else(unterminated_options)?;
+            let mut value = None;
+            if !matches!(tokens.peek(), Some(&",") | Some(&")")) {
+                value = Some(tokens.next().ok_or_else(unterminated_options)?);
+            }
+            options.push((name.to_ascii_lowercase(), value.map(unquote)));
+            match tokens.next() {
+                Some(",") => continue,
+                Some(")") => break,
+                _ => return Err(unterminated_options()),
+            }
+        }
+    } else {
+        while let Some(name) = tokens.next() {
+            let name = name.to_ascii_lowercase();
+            match name.as_str() {
+                "csv" => options.push(("format".to_owned(), Some("csv".to_owned()))),
+                "binary" => options.push(("format".to_owned(), Some("binary".to_owned()))),
+                "header" => options.push((name, None)),
+                "delimiter" | "null" => {
+                    if tokens
+                        .peek()
+                        .map_or(false, |token| token.eq_ignore_ascii_case("as"))
+                    {
+                        tokens.next();
+                    }
+                    let value = tokens.next().map(unquote);
+                    options.push((name, value));
+                }
+                _ => options.push((name, None)),
+            }
+        }
+    }
+
+    if tokens.next().is_some() {
+        return Err("syntax error after COPY options".to_owned());
+    }
+
+    copy_query(query, options)
+}
+
+fn copy_query(query: String, options: Vec<(String, Option<String>)>) -> Result<CopyQuery, String> {
+    let mut format = CopyFormat::Text;
+    let mut header = false;
+    let mut delimiter = None;
+    let mut null = None;
+
+    for (name, value) in options {
+        match (name.as_str(), value) {
+            ("format", Some(value)) => {
+                format = match value.to_ascii_lowercase().as_str() {
+                    "text" => CopyFormat::Text,
+                    "csv" => CopyFormat::Csv,
+                    _ => return Err(format!("COPY format \"{}\" is not supported", value)),
+                }
+            }
+            ("header", None) => header = true,
+            ("header", Some(value)) => {
+                header = match value.to_ascii_lowercase().as_str() {
+                    "true" | "on" | "1" => true,
+                    "false" | "off" | "0" => false,
+                    _ => {
+                        return Err(format!(
+                            "header requires a Boolean value, not \"{}\"",
+                            value
+                        ))
+                    }
+                }
+            }
+            ("delimiter", Some(value)) => {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => delimiter = Some(c),
+                    _ => return Err("COPY delimiter must be a single character".to_owned()),
+                }
+            }
+            ("null", Some(value)) => null = Some(value),
+            (name, _) => return Err(format!("COPY option \"{}\" is not supported", name)),
+        }
+    }
+
+    let csv = format == CopyFormat::Csv;
+    Ok(CopyQuery {
+        query,
+        delimiter: delimiter.unwrap_or(if csv { ',' } else { '\t' }),
+        null: null.unwrap_or_else(|| if csv { "" } else { "\\N" }.to_owned()),
+        format,
+        header,
+    })
+}
+
+/// Returns the index of the parenthesis closing the query that `rest` starts
+/// with, skipping parentheses inside string literals.
+fn query_end(rest: &str) -> Option<usize> {
+    if !rest.starts_with('(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for (index, c) in rest.char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '(' => depth += 1,
+            None if c == ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+/// Reads a string literal option value, whose doubled quotes stand for one.
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        Some(value) => value.replace("''", "'"),
+        None => value.to_owned(),
+    }
+}
+
+fn unterminated_options() -> String {
+    "syntax error in COPY options".to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(statement: &str) -> CopyQuery {
+        match parse_copy_query(statement) {
+            Some(Ok(copy_query)) => copy_query,
+            Some(Err(err)) => panic!("{}", err),
+            None => panic!("{} is not a COPY statement", statement),
+        }
+    }
+
+    fn fields(fields: &[Option<&str>]) -> impl Iterator<Item = Option<String>> + '_ {
+        fields.iter().map(|field| field.map(str::to_owned))
+    }
+
+    #[test]
+    fn copy_query_and_options_are_parsed() {
+        let copy_query =
+            parse("COPY (SELECT title FROM commits WHERE title = ')') TO STDOUT WITH (FORMAT csv, HEADER);");
+        assert_eq!(
+            copy_query.query,
+            "SELECT title FROM commits WHERE title = ')'"
+        );
+        assert!(copy_query.format == CopyFormat::Csv);
+        assert!(copy_query.header);
+        assert_eq!(copy_query.delimiter, ',');
+        assert_eq!(copy_query.null, "");
+
+        let copy_query = parse("copy (SELECT 1) to stdout delimiter as '|' null 'NULL'");
+        assert!(copy_query.format == CopyFormat::Text);
+        assert_eq!(copy_query.delimiter, '|');
+        assert_eq!(copy_query.null, "NULL");
+    }
+
+    #[test]
+    fn quoted_option_values_keep_doubled_quotes() {
+        let copy_query = parse("COPY (SELECT 1) TO STDOUT (DELIMITER '''', NULL 'it''s')");
+        assert_eq!(copy_query.delimiter, '\'');
+        assert_eq!(copy_query.null, "it's");
+
+        let copy_query = parse("COPY (SELECT 1) TO STDOUT NULL ''''''");
+        assert_eq!(copy_query.null, "''");
+    }
+
+    #[test]
+    fn unsupported_copy_forms_are_errors() {
+        for statement in [
+            "COPY commits TO STDOUT",
+            "COPY (SELECT 1) TO '/tmp/commits'",
+            "COPY (SELECT 1) FROM STDIN",
+            "COPY (SELECT 1) TO STDOUT BINARY",
+            "COPY (SELECT 1) TO STDOUT (DELIMITER ',,')",
+            "COPY (SELECT 1) TO STDOUT (FORMAT csv",
+        ] {
+            assert!(
+                matches!(parse_copy_query(statement), Some(Err(_))),
+                "{}",
+                statement
+            );
+        }
+        assert!(parse_copy_query("SELECT 1").is_none());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let copy_query = parse("COPY (SELECT 1) TO STDOUT (FORMAT csv)");
+        let line = copy_query.line(fields(&[
+            Some("a,b"),
+            Some("say \"hi\""),
+            Some("x\ny"),
+            Some("plain"),
+            Some(""),
+            None,
+        ]));
+        assert_eq!(line, "\"a,b\",\"say \"\"hi\"\"\",\"x\ny\",plain,\"\",\n");
+    }
+
+    #[test]
+    fn text_fields_are_escaped() {
+        let copy_query = parse("COPY (SELECT 1) TO STDOUT");
+        let line = copy_query.line(fields(&[
+            Some("a\tb"),
+            Some("back\\slash"),
+            Some("new\nline\r"),
+            None,
+        ]));
+        assert_eq!(line, "a\\tb\tback\\\\slash\tnew\\nline\\r\t\\N\n");
+
+        let copy_query = parse("COPY (SELECT 1) TO STDOUT (DELIMITER '|')");
+        assert_eq!(
+            copy_query.line(fields(&[Some("a|b"), Some("c")])),
+            "a\\|b|c\n"
+        );
+    }
+}
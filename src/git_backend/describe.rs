@@ -0,0 +1,161 @@
+use gitql_ast::environment::Environment;
+use gitql_ast::expression::Expression;
+use gitql_ast::object::{GitQLObject, Group};
+use gitql_ast::types::DataType;
+use gitql_engine::data_provider::{select_values, DataProvider};
+use pgwire::api::Type;
+use pgwire::error::PgWireResult;
+
+use crate::git_backend::git_schema::TABLES_FIELDS_TYPES;
+use crate::git_backend::parameter::{substitute_placeholders, BoundStatement};
+use crate::git_backend::statements::split_tokens;
+
+/// Data provider used to describe statements: tables produce no rows, so the
+/// engine only resolves the selected columns without touching a repository.
+pub struct SchemaDataProvider;
+
+impl DataProvider for SchemaDataProvider {
+    fn provide(
+        &self,
+        env: &mut Environment,
+        table: &str,
+        _fields_names: &[String],
+        titles: &[String],
+        fields_values: &[Box<dyn Expression>],
+    ) -> GitQLObject {
+        let group = if table.is_empty() {
+            select_values(env, titles, fields_values).unwrap_or(Group { rows: vec![] })
+        } else {
+            Group { rows: vec![] }
+        };
+
+        GitQLObject {
+            titles: titles.to_vec(),
+            groups: vec![group],
+        }
+    }
+}
+
+/// Infers the type of every `$n` placeholder from the column or clause it is
+/// used with, falling back to the type declared by the client and then text.
+pub fn infer_parameter_types(statement: &str, declared: &[Type]) -> Vec<DataType> {
+    let tokens = split_tokens(statement);
+    let count = tokens
+        .iter()
+        .filter_map(|token| placeholder_index(token))
+        .max()
+        .unwrap_or(0);
+
+    let mut types = (0..count)
+        .map(|index| {
+            declared
+                .get(index)
+                .and_then(declared_data_type)
+                .unwrap_or(DataType::Text)
+        })
+        .collect::<Vec<_>>();
+
+    for (position, token) in tokens.iter().enumerate() {
+        let index = match placeholder_index(token) {
+            Some(index) => index - 1,
+            None => continue,
+        };
+
+        if declared.get(index).and_then(declared_data_type).is_some() {
+            continue;
+        }
+
+        if let Some(data_type) = context_data_type(&tokens, position) {
+            types[index] = data_type;
+        }
+    }
+
+    types
+}
+
+/// Replaces every `$n` placeholder with a literal of its inferred type so the
+/// statement can be parsed before parameters are bound.
+pub fn bind_placeholder_literals(
+    statement: &str,
+    types: &[DataType],
+) -> PgWireResult<BoundStatement> {
+    substitute_placeholders(statement, |index| {
+        let data_type = types.get(index).unwrap_or(&DataType::Text);
+        Ok(placeholder_literal(data_type).to_owned())
+    })
+}
+
+/// The PostgreSQL type that parameters and columns of a GitQL type are
+/// described with.
+pub fn pg_type(data_type: &DataType) -> Type {
+    match data_type {
+        DataType::Integer => Type::INT8,
+        DataType::Float => Type::FLOAT8,
+        DataType::Boolean => Type::BOOL,
+        DataType::Date => Type::DATE,
+        DataType::Time => Type::TIME,
+        DataType::DateTime => Type::TIMESTAMP,
+        _ => Type::TEXT,
+    }
+}
+
+fn declared_data_type(declared: &Type) -> Option<DataType> {
+    match *declared {
+        Type::INT2 | Type::INT4 | Type::INT8 => Some(DataType::Integer),
+        Type::FLOAT4 | Type::FLOAT8 | Type::NUMERIC => Some(DataType::Float),
+        Type::BOOL => Some(DataType::Boolean),
+        Type::DATE => Some(DataType::Date),
+        Type::TIME => Some(DataType::Time),
+        Type::TIMESTAMP | Type::TIMESTAMPTZ => Some(DataType::DateTime),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => Some(DataType::Text),
+        _ => None,
+    }
+}
+
+fn context_data_type(tokens: &[String], position: usize) -> Option<DataType> {
+    let before = |offset: usize| position.checked_sub(offset).map(|index| &tokens[index]);
+    let after = |offset: usize| tokens.get(position + offset);
+
+    if let Some(keyword) = before(1) {
+        let keyword = keyword.to_lowercase();
+        if keyword == "limit" || keyword == "offset" {
+            return Some(DataType::Integer);
+        }
+    }
+
+    let column = match (before(1), before(2), after(1), after(2)) {
+        (Some(operator), Some(column), _, _) if is_comparison(operator) => column,
+        (_, _, Some(operator), Some(column)) if is_comparison(operator) => column,
+        _ => return None,
+    };
+
+    TABLES_FIELDS_TYPES
+        .get(column.to_lowercase().as_str())
+        .cloned()
+}
+
+fn is_comparison(token: &str) -> bool {
+    matches!(
+        token.to_lowercase().as_str(),
+        "=" | "!=" | "<>" | "<" | ">" | "<=" | ">=" | "like" | "glob" | "regexp"
+    )
+}
+
+fn placeholder_index(token: &str) -> Option<usize> {
+    token
+        .strip_prefix('$')
+        .and_then(|index| index.parse::<usize>().ok())
+        .filter(|index| *index > 0)
+}
+
+fn placeholder_literal(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Integer => "0",
+        DataType::Float => "0.0",
+        DataType::Boolean => "false",
+        DataType::Date => "\"2000-01-01\"",
+        DataType::Time => "\"00:00:00\"",
+        DataType::DateTime => "\"2000-01-01 00:00:00\"",
+        _ => "\"\"",
+    }
+}
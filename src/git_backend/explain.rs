@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use gitql_ast::statement::{LimitStatement, OffsetStatement, Query, SelectStatement};
+use gitql_ast::value::Value;
+use pgwire::api::Type;
+
+use super::git_data_provider::{repo_clear_name, revwalk_size};
+use super::query_context::QueryStats;
+use super::result_set::ResultSet;
+use super::streaming::statement;
+
+const DIFF_STATS_FIELDS: &[&str] = &["insertions", "deletions", "files_changed"];
+
+/// `EXPLAIN [ANALYZE] query`.
+pub struct ExplainQuery<'q> {
+    pub query: &'q str,
+    pub analyze: bool,
+}
+
+/// What running the query under `EXPLAIN ANALYZE` measured.
+pub struct Analysis {
+    pub stats: QueryStats,
+    pub rows: usize,
+    pub planning: Duration,
+    pub execution: Duration,
+}
+
+/// Recognizes an EXPLAIN statement, returning `None` for anything else and an
+/// error for an option that is not supported.
+pub fn parse_explain(statement: &str) -> Option<Result<ExplainQuery, String>> {
+    let statement = statement.trim().trim_end_matches(';').trim();
+    let keyword = statement.split_whitespace().next()?;
+    if !keyword.eq_ignore_ascii_case("explain") {
+        return None;
+    }
+
+    let mut rest = statement[keyword.len()..].trim_start();
+    let mut analyze = false;
+
+    if let Some(options) = rest.strip_prefix('(') {
+        let Some(end) = options.find(')') else {
+            return Some(Err("syntax error in EXPLAIN options".to_owned()));
+        };
+        for option in options[..end].split(',') {
+            let words = option.split_whitespace().collect::<Vec<_>>();
+            let (name, enabled) = match words.as_slice() {
+                [name] => (name.to_ascii_lowercase(), true),
+                [name, value] => match value.to_ascii_lowercase().as_str() {
+                    "true" | "on" | "1" => (name.to_ascii_lowercase(), true),
+                    "false" | "off" | "0" => (name.to_ascii_lowercase(), false),
+                    _ => {
+                        return Some(Err(format!(
+                            "invalid value for EXPLAIN option \"{}\"",
+                            name
+                        )))
+                    }
+                },
+                _ => return Some(Err("syntax error in EXPLAIN options".to_owned())),
+            };
+            match name.as_str() {
+                "analyze" | "analyse" => analyze = enabled,
+                "verbose" | "costs" | "timing" | "summary" => {}
+                _ => return Some(Err(format!("unrecognized EXPLAIN option \"{}\"", name))),
+            }
+        }
+        rest = options[end + 1..].trim_start();
+    } else {
+        while let Some(word) = rest.split_whitespace().next() {
+            match word.to_ascii_lowercase().as_str() {
+                "analyze" | "analyse" => analyze = true,
+                "verbose" => {}
+                _ => break,
+            }
+            rest = rest[word.len()..].trim_start();
+        }
+    }
+
+    Some(Ok(ExplainQuery {
+        query: rest,
+        analyze,
+    }))
+}
+
+/// The single column EXPLAIN returns.
+pub fn plan_columns() -> ResultSet {
+    ResultSet::new(&[("QUERY PLAN", Type::TEXT)])
+}
+
+/// Describes how the query is evaluated, one plan node per stage from the last
+/// stage applied down to the table scan, followed by the measurements of
+/// `analysis` when the query was run.
+pub fn explain(query: &Query, repos: &[gix::Repository], analysis: Option<&Analysis>) -> ResultSet {
+    let mut nodes = match query {
+        Query::Select(query) => {
+            let mut nodes = vec![];
+            if let Some(limit) = statement::<LimitStatement>(query, "limit") {
+                nodes.push(("Limit".to_owned(), vec![format!("Count: {}", limit.count)]));
+            }
+            if let Some(offset) = statement::<OffsetStatement>(query, "offset") {
+                nodes.push((
+                    "Offset".to_owned(),
+                    vec![format!("Count: {}", offset.count)],
+                ));
+            }
+            if query.statements.contains_key("order") {
+                nodes.push(("Sort".to_owned(), vec![]));
+            }
+            if query.statements.contains_key("having") {
+                nodes.push(("Filter".to_owned(), vec!["Clause: HAVING".to_owned()]));
+            }
+
+            let select = statement::<SelectStatement>(query, "select");
+            if select.map_or(false, |select| select.is_distinct) {
+                nodes.push(("Unique".to_owned(), vec![]));
+            }
+            if query.has_group_by_statement {
+                nodes.push(("Group".to_owned(), vec![]));
+            } else if query.has_aggregation_function {
+                nodes.push(("Aggregate".to_owned(), vec![]));
+            }
+            if query.statements.contains_key("where") {
+                nodes.push(("Filter".to_owned(), vec!["Clause: WHERE".to_owned()]));
+            }
+
+            match select.filter(|select| !select.table_name.is_empty()) {
+                Some(select) => nodes.push(scan(select, repos, analysis)),
+                None => nodes.push(("Result".to_owned(), vec![])),
+            }
+            nodes
+        }
+        _ => vec![("Result".to_owned(), vec![])],
+    };
+
+    if let Some(analysis) = analysis {
+        nodes[0].0 = format!("{} (actual rows={})", nodes[0].0, analysis.rows);
+    }
+
+    let mut result = plan_columns();
+    for (depth, (node, details)) in nodes.into_iter().enumerate() {
+        let (node_indent, detail_indent) = match depth {
+            0 => (String::new(), 2),
+            depth => (format!("{}->  ", " ".repeat(6 * depth - 4)), 6 * depth + 2),
+        };
+        result
+            .rows
+            .push(vec![Value::Text(format!("{}{}", node_indent, node))]);
+        for detail in details {
+            let line = format!("{}{}", " ".repeat(detail_indent), detail);
+            result.rows.push(vec![Value::Text(line)]);
+        }
+    }
+
+    if let Some(analysis) = analysis {
+        for (name, duration) in [
+            ("Planning Time", analysis.planning),
+            ("Execution Time", analysis.execution),
+        ] {
+            let line = format!("{}: {}", name, milliseconds(duration));
+            result.rows.push(vec![Value::Text(line)]);
+        }
+    }
+
+    result
+}
+
+fn scan(
+    select: &SelectStatement,
+    repos: &[gix::Repository],
+    analysis: Option<&Analysis>,
+) -> (String, Vec<String>) {
+    let table = select.table_name.as_str();
+    let mut details = vec![format!("Columns: {}", select.fields_names.join(", "))];
+
+    for repo in repos {
+        let name = repo_clear_name(repo);
+        let mut line = format!("Repository: {}", name);
+        let walks_commits = matches!(table, "commits" | "diffs");
+        if walks_commits && analysis.is_none() {
+            line += &format!(" (revwalk: {} commits)", revwalk_size(repo));
+        }
+
+        if let Some(analysis) = analysis {
+            let measured = analysis
+                .stats
+                .repositories
+                .iter()
+                .filter(|stats| stats.name == name && stats.table == table);
+            let (duration, rows, commits) = measured.fold(
+                (Duration::ZERO, 0, 0),
+                |(duration, rows, commits), stats| {
+                    (
+                        duration + stats.duration,
+                        rows + stats.rows,
+                        commits + stats.commits,
+                    )
+                },
+            );
+            line += &format!(" (actual time={} rows={}", milliseconds(duration), rows);
+            if walks_commits {
+                line += &format!(" commits={}", commits);
+            }
+            line.push(')');
+        }
+        details.push(line);
+    }
+
+    if table == "diffs" {
+        let computed = select
+            .fields_names
+            .iter()
+            .any(|field| DIFF_STATS_FIELDS.contains(&field.as_str()));
+        details.push(format!(
+            "Diff Statistics: {}",
+            if computed {
+                "computed for every commit"
+            } else {
+                "not computed"
+            }
+        ));
+    }
+
+    (format!("Scan on {}", table), details)
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
@@ -1,79 +1,72 @@
-use std::io::{Error, ErrorKind};
+use std::collections::HashMap;
 
+use gitql_ast::environment::Environment;
+use gitql_ast::statement::{Query, SelectStatement};
 use gitql_ast::types::DataType;
 
 use pgwire::api::portal::Format;
-use pgwire::api::results::FieldInfo;
+use pgwire::api::results::{FieldFormat, FieldInfo};
 use pgwire::api::Type;
-use pgwire::error::{PgWireError, PgWireResult};
 
+use crate::git_backend::describe::pg_type;
 use crate::git_backend::git_schema::TABLES_FIELDS_TYPES;
+use crate::git_backend::streaming::statement;
 
-pub fn encode_column(string: &str, index: usize) -> PgWireResult<FieldInfo> {
-    let column_type = TABLES_FIELDS_TYPES.get(string);
-    if column_type.is_none() {
-        return Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::INT8,
-            Format::UnifiedText.format_for(index),
-        ));
-    }
+/// The type of every column a query selects, keyed by its title.
+pub type ColumnTypes = HashMap<String, DataType>;
 
-    match column_type.unwrap() {
-        DataType::Text => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::TEXT,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::Integer => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::INT8,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::Float => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::FLOAT8,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::Boolean => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::BOOL,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::Time => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::TIME,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::Date => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::DATE,
-            Format::UnifiedText.format_for(index),
-        )),
-        DataType::DateTime => Ok(FieldInfo::new(
-            String::from(string),
-            None,
-            None,
-            Type::TIME,
-            Format::UnifiedText.format_for(index),
-        )),
-        _ => Err(PgWireError::IoError(Error::new(
-            ErrorKind::Other,
-            "Failed to get type of data",
-        ))),
-    }
+/// Types the columns of `query` from the expressions they were parsed from,
+/// so a statement is described with the types it is executed with, whether
+/// or not it returns rows.
+pub fn column_types(env: &Environment, query: &Query) -> ColumnTypes {
+    let select = match query {
+        Query::Select(query) => statement::<SelectStatement>(query, "select"),
+        _ => None,
+    };
+    let Some(select) = select else {
+        return ColumnTypes::new();
+    };
+
+    select
+        .fields_names
+        .iter()
+        .zip(&select.fields_values)
+        .map(|(name, value)| {
+            let title = select.alias_table.get(name).unwrap_or(name);
+            (title.clone(), value.expr_type(env))
+        })
+        .filter(|(_, data_type)| is_column_type(data_type))
+        .collect()
+}
+
+pub fn encode_column(
+    string: &str,
+    data_type: Option<&DataType>,
+    index: usize,
+    format: &Format,
+) -> FieldInfo {
+    // A column whose type is only known once evaluated is described as TEXT,
+    // so its values are sent as text whatever they are: a binary integer or
+    // float would not read as TEXT.
+    let (column_type, field_format) = match data_type.or_else(|| TABLES_FIELDS_TYPES.get(string)) {
+        Some(data_type) => (pg_type(data_type), format.format_for(index)),
+        None => (Type::TEXT, FieldFormat::Text),
+    };
+
+    FieldInfo::new(String::from(string), None, None, column_type, field_format)
+}
+
+/// Tells whether values of `data_type` all have one PostgreSQL type, unlike
+/// the result of an expression whose type is only known once evaluated.
+fn is_column_type(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Text
+            | DataType::Integer
+            | DataType::Float
+            | DataType::Boolean
+            | DataType::Date
+            | DataType::Time
+            | DataType::DateTime
+    )
 }
@@ -1,5 +1,7 @@
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 use gitql_ast::environment::Environment;
 use gitql_ast::expression::Expression;
@@ -9,18 +11,77 @@ use gitql_ast::object::Row;
 use gitql_engine::data_provider::select_values;
 use gitql_engine::data_provider::DataProvider;
 use gitql_engine::engine_evaluator::evaluate_expression;
+use gix::object::tree::diff::change::Event;
 use gix::refs::Category;
 
 use gitql_ast::expression::SymbolExpression;
 use gitql_ast::value::Value;
 
+use super::query_context::RepositoryStats;
+use super::QueryContext;
+use crate::metrics;
+
 pub struct GitDataProvider {
     pub repos: Vec<gix::Repository>,
+    context: Arc<QueryContext>,
 }
 
+/// Receives every row as soon as a table produces it; returning `false` stops
+/// the walk of the current repository.
+pub type RowEmitter<'a> = dyn FnMut(&mut Environment, Row) -> Result<bool, String> + 'a;
+
 impl GitDataProvider {
-    pub fn new(repos: Vec<gix::Repository>) -> Self {
-        Self { repos }
+    pub fn new(repos: Vec<gix::Repository>, context: Arc<QueryContext>) -> Self {
+        Self { repos, context }
+    }
+
+    /// Walks the repositories one after another and hands every row to `emit`
+    /// without collecting them, stopping once `emit` returns `false`.
+    pub fn for_each_row(
+        &self,
+        env: &mut Environment,
+        table: &str,
+        fields_names: &[String],
+        titles: &[String],
+        fields_values: &[Box<dyn Expression>],
+        emit: &mut RowEmitter,
+    ) -> Result<(), String> {
+        for repository in &self.repos {
+            self.context.check()?;
+
+            let started = Instant::now();
+            let commits = self.context.commits();
+            let mut rows = 0;
+            let mut stopped = false;
+            let result = select_gql_objects(
+                env,
+                &self.context,
+                repository,
+                table.to_string(),
+                fields_names,
+                titles,
+                fields_values,
+                &mut |env: &mut Environment, row: Row| {
+                    rows += 1;
+                    stopped = !emit(env, row)?;
+                    Ok(!stopped)
+                },
+            );
+            self.context.record_repository(RepositoryStats {
+                name: repo_clear_name(repository),
+                table: table.to_owned(),
+                duration: started.elapsed(),
+                rows,
+                commits: self.context.commits() - commits,
+            });
+
+            result?;
+            if stopped {
+                break;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -36,16 +97,38 @@ impl DataProvider for GitDataProvider {
         let mut groups: Vec<Group> = vec![];
 
         for repository in &self.repos {
-            let repository_group = select_gql_objects(
+            if self.context.check().is_err() {
+                break;
+            }
+
+            let started = Instant::now();
+            let commits = self.context.commits();
+            let mut rows: Vec<Row> = vec![];
+            let repository_result = select_gql_objects(
                 env,
+                &self.context,
                 repository,
                 table.to_string(),
                 fields_names,
                 titles,
                 fields_values,
+                &mut |_: &mut Environment, row: Row| {
+                    self.context.count_row(&row)?;
+                    rows.push(row);
+                    Ok(true)
+                },
             );
 
-            if let Ok(mut group) = repository_group {
+            self.context.record_repository(RepositoryStats {
+                name: repo_clear_name(repository),
+                table: table.to_owned(),
+                duration: started.elapsed(),
+                rows: rows.len(),
+                commits: self.context.commits() - commits,
+            });
+
+            if repository_result.is_ok() {
+                let mut group = Group { rows };
                 if groups.is_empty() {
                     groups.push(group);
                 } else {
@@ -61,35 +144,86 @@ impl DataProvider for GitDataProvider {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn select_gql_objects(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     table: String,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     match table.as_str() {
-        "refs" => select_references(env, repo, fields_names, titles, fields_values),
-        "commits" => select_commits(env, repo, fields_names, titles, fields_values),
-        "branches" => select_branches(env, repo, fields_names, titles, fields_values),
-        "diffs" => select_diffs(env, repo, fields_names, titles, fields_values),
-        "tags" => select_tags(env, repo, fields_names, titles, fields_values),
-        _ => select_values(env, titles, fields_values),
+        "refs" => select_references(
+            env,
+            context,
+            repo,
+            fields_names,
+            titles,
+            fields_values,
+            emit,
+        ),
+        "commits" => select_commits(
+            env,
+            context,
+            repo,
+            fields_names,
+            titles,
+            fields_values,
+            emit,
+        ),
+        "branches" => select_branches(
+            env,
+            context,
+            repo,
+            fields_names,
+            titles,
+            fields_values,
+            emit,
+        ),
+        "diffs" => select_diffs(
+            env,
+            context,
+            repo,
+            fields_names,
+            titles,
+            fields_values,
+            emit,
+        ),
+        "tags" => select_tags(
+            env,
+            context,
+            repo,
+            fields_names,
+            titles,
+            fields_values,
+            emit,
+        ),
+        _ => {
+            for row in select_values(env, titles, fields_values)?.rows {
+                if !emit(env, row)? {
+                    break;
+                }
+            }
+            Ok(())
+        }
     }
 }
 
 fn select_references(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
-    let mut rows: Vec<Row> = vec![];
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     let git_references = repo.references();
     if git_references.is_err() {
-        return Ok(Group { rows });
+        return Ok(());
     }
 
     let references = git_references.ok().unwrap();
@@ -98,6 +232,7 @@ fn select_references(
     let padding = names_len - values_len;
 
     for reference in references.all().unwrap().flatten() {
+        context.check()?;
         let mut values: Vec<Value> = Vec::with_capacity(fields_names.len());
 
         for index in 0..names_len {
@@ -153,24 +288,26 @@ fn select_references(
             values.push(Value::Null);
         }
 
-        let row = Row { values };
-        rows.push(row);
+        if !emit(env, Row { values })? {
+            break;
+        }
     }
 
-    Ok(Group { rows })
+    Ok(())
 }
 
 fn select_commits(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
-    let mut rows: Vec<Row> = vec![];
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     let head_id = repo.head_id();
     if head_id.is_err() {
-        return Ok(Group { rows });
+        return Ok(());
     }
 
     let revwalk = head_id.unwrap().ancestors().all().unwrap();
@@ -180,6 +317,7 @@ fn select_commits(
     let padding = names_len - values_len;
 
     for commit_info in revwalk {
+        context.count_commit()?;
         let commit_info = commit_info.unwrap();
         let commit = repo.find_object(commit_info.id).unwrap().into_commit();
         let commit = commit.decode().unwrap();
@@ -244,34 +382,35 @@ fn select_commits(
             values.push(Value::Null);
         }
 
-        let row = Row { values };
-        rows.push(row);
+        if !emit(env, Row { values })? {
+            break;
+        }
     }
 
-    Ok(Group { rows })
+    Ok(())
 }
 
 fn select_branches(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
-    let mut rows: Vec<Row> = vec![];
-
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     let platform = repo.references().unwrap();
     let local_branches = platform.local_branches().unwrap();
     let remote_branches = platform.remote_branches().unwrap();
     let local_and_remote_branches = local_branches.chain(remote_branches);
     let head_ref_result = repo.head_ref();
     if head_ref_result.is_err() {
-        return Ok(Group { rows });
+        return Ok(());
     }
 
     let head_ref_option = head_ref_result.unwrap();
     if head_ref_option.is_none() {
-        return Ok(Group { rows });
+        return Ok(());
     }
 
     let head_ref = head_ref_option.unwrap();
@@ -281,6 +420,7 @@ fn select_branches(
     let padding = names_len - values_len;
 
     for branch in local_and_remote_branches.flatten() {
+        context.check()?;
         let mut values: Vec<Value> = Vec::with_capacity(fields_names.len());
 
         for index in 0..names_len {
@@ -302,15 +442,16 @@ fn select_branches(
             }
 
             if field_name == "commit_count" {
-                let commit_count = if let Some(id) = branch.try_id() {
+                let mut commit_count = -1;
+                if let Some(id) = branch.try_id() {
                     if let Ok(revwalk) = id.ancestors().all() {
-                        revwalk.count() as i64
-                    } else {
-                        -1
+                        commit_count = 0;
+                        for _ in revwalk {
+                            context.count_commit()?;
+                            commit_count += 1;
+                        }
                     }
-                } else {
-                    -1
-                };
+                }
                 values.push(Value::Integer(commit_count));
                 continue;
             }
@@ -337,27 +478,29 @@ fn select_branches(
             values.push(Value::Null);
         }
 
-        let row = Row { values };
-        rows.push(row);
+        if !emit(env, Row { values })? {
+            break;
+        }
     }
 
-    Ok(Group { rows })
+    Ok(())
 }
 
 fn select_diffs(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     let repo = {
         let mut repo = repo.clone();
         repo.object_cache_size_if_unset(4 * 1024 * 1024);
         repo
     };
 
-    let mut rows: Vec<Row> = vec![];
     let revwalk = repo.head_id().unwrap().ancestors().all().unwrap();
 
     let mut rewrite_cache = repo
@@ -370,6 +513,7 @@ fn select_diffs(
     let padding = names_len - values_len;
 
     for commit_info in revwalk {
+        context.count_commit()?;
         let commit_info = commit_info.unwrap();
         let commit = commit_info.id().object().unwrap().into_commit();
 
@@ -428,7 +572,8 @@ fn select_diffs(
 
                 let (mut insertions, mut deletions, mut files_changed) = (0, 0, 0);
 
-                previous
+                metrics::DIFF_COMPUTATIONS.inc();
+                let diff_result = previous
                     .changes()
                     .unwrap()
                     .for_each_to_obtain_tree_with_cache(
@@ -436,7 +581,14 @@ fn select_diffs(
                         &mut rewrite_cache,
                         |change| -> Result<_, gix::object::blob::diff::init::Error> {
                             files_changed += usize::from(change.event.entry_mode().is_no_tree());
-                            if select_insertions_or_deletions {
+                            if select_insertions_or_deletions
+                                && change.event.entry_mode().is_no_tree()
+                            {
+                                let blob_bytes = change_blob_bytes(&repo, &change.event);
+                                if context.count_diff_bytes(blob_bytes).is_err() {
+                                    return Ok(gix::object::tree::diff::Action::Cancel);
+                                }
+
                                 if let Ok(mut platform) = change.diff(&mut diff_cache) {
                                     if let Ok(Some(counts)) = platform.line_counts() {
                                         deletions += counts.removals;
@@ -446,8 +598,9 @@ fn select_diffs(
                             }
                             Ok(gix::object::tree::diff::Action::Continue)
                         },
-                    )
-                    .unwrap();
+                    );
+                context.check()?;
+                diff_result.unwrap();
 
                 if field_name == "insertions" {
                     values.push(Value::Integer(insertions as i64));
@@ -468,20 +621,23 @@ fn select_diffs(
             values.push(Value::Null);
         }
 
-        let row = Row { values };
-        rows.push(row);
+        if !emit(env, Row { values })? {
+            break;
+        }
     }
 
-    Ok(Group { rows })
+    Ok(())
 }
 
 fn select_tags(
     env: &mut Environment,
+    context: &QueryContext,
     repo: &gix::Repository,
     fields_names: &[String],
     titles: &[String],
     fields_values: &[Box<dyn Expression>],
-) -> Result<Group, String> {
+    emit: &mut RowEmitter,
+) -> Result<(), String> {
     let platform = repo.references().unwrap();
     let tag_names = platform.tags().unwrap();
 
@@ -489,9 +645,8 @@ fn select_tags(
     let values_len = fields_values.len() as i64;
     let padding = names_len - values_len;
 
-    let mut rows: Vec<Row> = vec![];
-
     for tag_ref in tag_names.flatten() {
+        context.check()?;
         let mut values: Vec<Value> = Vec::with_capacity(fields_names.len());
 
         for index in 0..names_len {
@@ -523,14 +678,39 @@ fn select_tags(
             values.push(Value::Null);
         }
 
-        let row = Row { values };
-        rows.push(row);
+        if !emit(env, Row { values })? {
+            break;
+        }
     }
 
-    Ok(Group { rows })
+    Ok(())
+}
+
+fn change_blob_bytes(repo: &gix::Repository, event: &Event<'_, '_, '_>) -> u64 {
+    let ids = match event {
+        Event::Addition { id, .. } | Event::Deletion { id, .. } => vec![id.detach()],
+        Event::Modification {
+            previous_id, id, ..
+        } => vec![previous_id.detach(), id.detach()],
+        Event::Rewrite { source_id, id, .. } => vec![source_id.detach(), id.detach()],
+    };
+
+    ids.into_iter()
+        .filter_map(|id| repo.find_header(id).ok())
+        .map(|header| header.size())
+        .sum()
+}
+
+/// Counts the commits reachable from HEAD, which the commits and diffs tables
+/// walk in every repository. Only ancestor ids are read, not trees or diffs.
+pub fn revwalk_size(repo: &gix::Repository) -> usize {
+    repo.head_id()
+        .ok()
+        .and_then(|head_id| head_id.ancestors().all().ok())
+        .map_or(0, |revwalk| revwalk.count())
 }
 
-fn repo_clear_name(repo: &gix::Repository) -> String {
+pub fn repo_clear_name(repo: &gix::Repository) -> String {
     let new = fs::canonicalize(repo.path().parent().unwrap())
         .ok()
         .unwrap();
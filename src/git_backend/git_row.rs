@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use chrono::{NaiveDateTime, NaiveTime};
 use futures::{stream, Stream};
 
 use gitql_ast::object::GitQLObject;
@@ -16,21 +17,64 @@ pub fn encode_row(
     let mut elements = vec![];
 
     for row in &groups.groups[0].rows {
-        let mut encoder = DataRowEncoder::new(fields_info.clone().into());
-        for value in row.values.iter() {
-            match value {
-                Value::Text(text) => encoder.encode_field(&text).unwrap(),
-                Value::Integer(int) => encoder.encode_field(&int).unwrap(),
-                Value::Float(float) => encoder.encode_field(&float).unwrap(),
-                Value::Boolean(bool) => encoder.encode_field(&bool).unwrap(),
-                Value::Time(time) => encoder.encode_field(&time).unwrap(),
-                Value::Date(date) => encoder.encode_field(&date).unwrap(),
-                Value::DateTime(date) => encoder.encode_field(&date).unwrap(),
-                _ => encoder.encode_field(&None::<i8>).unwrap(),
-            }
-        }
-        elements.push(encoder.finish());
+        elements.push(encode_values(&row.values, fields_info.clone()));
     }
 
     stream::iter(elements.into_iter())
 }
+
+pub fn encode_values<'v>(
+    values: impl IntoIterator<Item = &'v Value>,
+    fields_info: Arc<Vec<FieldInfo>>,
+) -> PgWireResult<DataRow> {
+    let mut encoder = DataRowEncoder::new(fields_info);
+    for value in values {
+        encode_value(&mut encoder, value)?;
+    }
+    encoder.finish()
+}
+
+/// Encodes one value in the text or binary format of its column.
+pub fn encode_value(encoder: &mut DataRowEncoder, value: &Value) -> PgWireResult<()> {
+    match value {
+        Value::Text(text) => encoder.encode_field(&text),
+        Value::Integer(int) => encoder.encode_field(&int),
+        Value::Float(float) => encoder.encode_field(&float),
+        Value::Boolean(bool) => encoder.encode_field(&bool),
+        Value::Time(time) => match parse_time(time) {
+            Some(time) => encoder.encode_field(&time),
+            None => encoder.encode_field(&None::<i8>),
+        },
+        Value::Date(date) => match timestamp(*date) {
+            Some(date) => encoder.encode_field(&date.date()),
+            None => encoder.encode_field(&None::<i8>),
+        },
+        Value::DateTime(date) => match timestamp(*date) {
+            Some(date) => encoder.encode_field(&date),
+            None => encoder.encode_field(&None::<i8>),
+        },
+        _ => encoder.encode_field(&None::<i8>),
+    }
+}
+
+/// Formats one value as its column's text format, or `None` for NULL.
+pub fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Text(text) => Some(text.clone()),
+        Value::Integer(int) => Some(int.to_string()),
+        Value::Float(float) => Some(float.to_string()),
+        Value::Boolean(bool) => Some(if *bool { "t" } else { "f" }.to_owned()),
+        Value::Time(time) => parse_time(time).map(|time| time.to_string()),
+        Value::Date(date) => timestamp(*date).map(|date| date.date().to_string()),
+        Value::DateTime(date) => timestamp(*date).map(|date| date.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S%.f").ok()
+}
+
+fn timestamp(seconds: i64) -> Option<NaiveDateTime> {
+    NaiveDateTime::from_timestamp_opt(seconds, 0)
+}
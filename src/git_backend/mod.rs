@@ -1,158 +1,134 @@
 use std::collections::HashMap;
+use std::fmt::Debug;
 use std::fs;
-use std::io::{Error, ErrorKind};
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, Peekable};
+use futures::{Sink, SinkExt, StreamExt};
 use gitql_ast::object::GitQLObject;
-use pgwire::api::portal::Portal;
+use pgwire::api::auth::DefaultServerParameterProvider;
+use pgwire::api::portal::{Format, Portal};
 use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
 use pgwire::api::results::{
-    DescribePortalResponse, DescribeStatementResponse, FieldInfo, QueryResponse, Response, Tag,
+    CopyResponse, DescribePortalResponse, DescribeStatementResponse, FieldInfo, QueryResponse,
+    Response, Tag,
 };
 use pgwire::api::stmt::{NoopQueryParser, StoredStatement};
-use pgwire::api::{ClientInfo, MakeHandler};
-use pgwire::error::{PgWireError, PgWireResult};
+use pgwire::api::store::PortalStore;
+use pgwire::api::{
+    ClientInfo, ClientPortalStore, MakeHandler, DEFAULT_NAME, METADATA_DATABASE, METADATA_USER,
+};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::data::DataRow;
+use pgwire::messages::extendedquery::{Execute, PortalSuspended};
+use pgwire::messages::response::TransactionStatus;
+use pgwire::messages::PgWireBackendMessage;
 
+use crate::acl::RepositoryAcl;
+use crate::audit::AuditLog;
+use crate::config::{QueryLimits, QueryLimitsConfig};
+use crate::metrics;
+use crate::session::{QueryGuard, SessionRegistry};
+use crate::unix_socket::is_local_addr;
 use git_data_provider::GitDataProvider;
 use git_schema::TABLES_FIELDS_NAMES;
 use git_schema::TABLES_FIELDS_TYPES;
 use gitql_ast::environment::Environment;
 use gitql_ast::schema::Schema;
+use gitql_ast::statement::Query;
 use gitql_engine::data_provider::DataProvider;
 use gitql_engine::engine::{self, EvaluationResult::SelectedGroups};
+use gitql_parser::diagnostic::Diagnostic;
 use gitql_parser::parser;
 use gitql_parser::tokenizer;
 
-use git_column::encode_column;
-use git_row::encode_row;
-use parameter::make_qeury;
+use catalog::{catalog_query, CatalogSession};
+use copy::{parse_copy_query, CopyQuery};
+use describe::{bind_placeholder_literals, infer_parameter_types, pg_type, SchemaDataProvider};
+use explain::{explain, parse_explain, plan_columns, Analysis, ExplainQuery};
+use git_column::{column_types, encode_column, ColumnTypes};
+use git_row::{encode_row, encode_values};
+use parameter::{bind_parameters, BoundStatement};
+use query_log::QueryRecorder;
+use result_set::ResultSet;
+use session_command::{parse_session_command, SessionCommand};
+use settings::{parse_settings_query, Settings};
+use statement_timeout::parse_statement_timeout;
+use statements::{first_statement, split_statements};
+use streaming::{stream_rows, streaming_titles};
+
+use query_context::Interruption;
+pub use query_context::QueryContext;
 
+mod catalog;
+mod copy;
+mod describe;
+mod explain;
 mod git_column;
 mod git_data_provider;
 mod git_row;
 mod git_schema;
 mod parameter;
+mod query_context;
+mod query_log;
+mod result_set;
+mod session_command;
+mod settings;
+mod statement_timeout;
+mod statements;
+mod streaming;
 
 pub struct GitQLBackend {
     repositories: Arc<[String]>,
+    acl: Arc<RepositoryAcl>,
+    sessions: Arc<SessionRegistry>,
     query_parser: Arc<NoopQueryParser>,
-    query_cache: Arc<Mutex<HashMap<String, GitQLObject>>>,
+    query_cache: Arc<Mutex<HashMap<String, (GitQLObject, ColumnTypes, Arc<QueryContext>)>>>,
+    query_limits: Arc<QueryLimitsConfig>,
+    audit_log: Option<Arc<AuditLog>>,
+    default_statement_timeout: Option<Duration>,
+    statement_timeout: Mutex<Option<Duration>>,
+    portal_cursors: Mutex<HashMap<String, PortalCursor>>,
+    session_parameters: Mutex<HashMap<String, String>>,
+    /// Reported settings changed by session commands, sent to the client with
+    /// ParameterStatus once the current message is processed.
+    parameter_reports: Mutex<Vec<(String, String)>>,
+    client_releases: Mutex<Vec<ClientRelease>>,
+    server_parameters: Arc<DefaultServerParameterProvider>,
 }
 
 #[async_trait]
 impl SimpleQueryHandler for GitQLBackend {
     async fn do_query<'a, C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         query: &'a str,
     ) -> PgWireResult<Vec<Response<'a>>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let git_repo_result = validate_git_repositories(&self.repositories);
-        if query.to_uppercase().starts_with("DEALLOCATE") {
-            return Ok(vec![Response::Execution(Tag::new("OK").with_rows(1))]);
+        let statements = split_statements(query);
+        if statements.is_empty() {
+            return Ok(vec![Response::EmptyQuery]);
         }
 
-        if git_repo_result.is_err() {
-            println!("Failed to load git repositories");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                git_repo_result.err().unwrap(),
-            )));
-        }
-
-        let repos = git_repo_result.ok().unwrap();
-        let schema = Schema {
-            tables_fields_names: TABLES_FIELDS_NAMES.to_owned(),
-            tables_fields_types: TABLES_FIELDS_TYPES.to_owned(),
-        };
-
-        let mut env = Environment::new(schema);
-        let query = query.split(';').next().unwrap();
-        let tokenizer_result = tokenizer::tokenize(query.to_string());
-        if tokenizer_result.is_err() {
-            println!("Cannot tokenize result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                tokenizer_result.err().unwrap().message().to_owned(),
-            )));
-        }
-
-        let tokens = tokenizer_result.ok().unwrap();
-        if tokens.is_empty() {
-            println!("Empty Tokens");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                "Empty Tokens",
-            )));
-        }
-
-        let parser_result = parser::parse_gql(tokens, &mut env);
-        if parser_result.is_err() {
-            let parser_err = parser_result.err().unwrap();
-            let error_message =
-                parser_err.message().to_owned() + "\nHelp: " + &parser_err.helps().join("\n");
-            println!("Cannot parse result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                error_message,
-            )));
-        }
-
-        let query_node = parser_result.ok().unwrap();
-
-        let provider: Box<dyn DataProvider> = Box::new(GitDataProvider::new(repos.to_vec()));
-        let evaluation_result = engine::evaluate(&mut env, &provider, query_node);
-
-        if evaluation_result.is_err() {
-            println!("Cannot evaluate result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                evaluation_result.err().unwrap(),
-            )));
-        }
-        let engine_result = evaluation_result.ok().unwrap();
-
-        if let SelectedGroups(mut groups, hidden_selection) = engine_result {
-            let mut indexes = vec![];
-            for (index, title) in groups.titles.iter().enumerate() {
-                if hidden_selection.contains(title) {
-                    indexes.insert(0, index);
+        let mut responses = vec![];
+        for (index, (offset, statement)) in statements.iter().copied().enumerate() {
+            let is_last = index + 1 == statements.len();
+            match self.execute_statement(client, statement, is_last) {
+                Ok(response) => responses.push(response),
+                Err(err) => {
+                    let err = shifted_error(err, query, offset);
+                    responses.push(Response::Error(Box::new(err.into())));
+                    break;
                 }
             }
-
-            if groups.len() > 1 {
-                groups.flat();
-            }
-
-            for index in indexes {
-                groups.titles.remove(index);
-
-                for row in &mut groups.groups[0].rows {
-                    row.values.remove(index);
-                }
-            }
-
-            let mut fields_info: Vec<FieldInfo> = vec![];
-
-            for (index, title) in groups.titles.iter().enumerate() {
-                let field_result = encode_column(title, index);
-                if field_result.is_err() {
-                    continue;
-                }
-                fields_info.push(field_result.ok().unwrap());
-            }
-
-            let result = encode_row(&groups, Arc::new(fields_info.clone()));
-            return Ok(vec![Response::Query(QueryResponse::new(
-                Arc::new(fields_info),
-                result,
-            ))]);
         }
 
-        Ok(vec![Response::Execution(Tag::new("OK").with_rows(1))])
+        Ok(responses)
     }
 }
 
@@ -167,174 +143,1082 @@ impl ExtendedQueryHandler for GitQLBackend {
 
     async fn do_query<'a, 'b: 'a, C>(
         &'b self,
-        _client: &mut C,
+        client: &mut C,
         portal: &'a Portal<Self::Statement>,
         _max_rows: usize,
     ) -> PgWireResult<Response<'a>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let query = &make_qeury(portal);
-        if query.to_lowercase().starts_with("set") {
-            return Ok(Response::Execution(Tag::new("OK").with_rows(1)));
+        match self.execute_portal(client, portal)? {
+            PortalResult::Execution(tag) => Ok(Response::Execution(tag)),
+            PortalResult::Session(tag, transaction_status) => {
+                Ok(session_response(tag, transaction_status))
+            }
+            PortalResult::Rows(fields_info, data_rows) => {
+                Ok(Response::Query(QueryResponse::new(fields_info, data_rows)))
+            }
+            PortalResult::Streamed(streamed) => {
+                let query_guard = self
+                    .sessions
+                    .begin_query(&client.socket_addr(), streamed.context);
+                let data_rows = guarded(streamed.data_rows, query_guard);
+                Ok(Response::Query(QueryResponse::new(
+                    streamed.fields_info,
+                    data_rows,
+                )))
+            }
         }
+    }
+
+    async fn on_execute<C>(&self, client: &mut C, message: Execute) -> PgWireResult<()>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let portal_name = message.name().as_deref().unwrap_or(DEFAULT_NAME).to_owned();
+        let portal = client
+            .portal_store()
+            .get_portal(&portal_name)
+            .ok_or_else(|| PgWireError::PortalNotFound(portal_name.clone()))?;
+        let max_rows = usize::try_from(*message.max_rows()).unwrap_or(0);
 
-        let locked_cache = self.query_cache.lock().unwrap();
-        match locked_cache.get(query) {
-            Some(groups) => {
-                let mut fields_info: Vec<FieldInfo> = vec![];
+        let suspended = {
+            let store = client.portal_store();
+            let mut cursors = self.portal_cursors.lock().unwrap();
+            cursors.retain(|name, cursor| {
+                store
+                    .get_portal(name)
+                    .map_or(false, |portal| Arc::ptr_eq(&portal, &cursor.portal))
+            });
+            cursors.remove(&portal_name)
+        };
 
-                for (index, title) in groups.titles.iter().enumerate() {
-                    let field_result = encode_column(title, index);
-                    if field_result.is_err() {
-                        continue;
+        let mut cursor = match suspended {
+            Some(cursor) => cursor,
+            None => match self.execute_portal(client, &portal)? {
+                PortalResult::Execution(tag) => {
+                    client
+                        .feed(PgWireBackendMessage::CommandComplete(tag.into()))
+                        .await?;
+                    return Ok(());
+                }
+                PortalResult::Session(tag, transaction_status) => {
+                    if let Some(transaction_status) = transaction_status {
+                        client.set_transaction_status(transaction_status);
                     }
-                    fields_info.push(field_result.ok().unwrap());
+                    client
+                        .feed(PgWireBackendMessage::CommandComplete(tag.into()))
+                        .await?;
+                    return Ok(());
                 }
+                PortalResult::Rows(_, data_rows) => PortalCursor {
+                    portal: portal.clone(),
+                    data_rows: data_rows.peekable(),
+                    sent_rows: 0,
+                    context: None,
+                },
+                PortalResult::Streamed(streamed) => PortalCursor {
+                    portal: portal.clone(),
+                    data_rows: streamed.data_rows.peekable(),
+                    sent_rows: 0,
+                    context: Some(streamed.context),
+                },
+            },
+        };
 
-                let result = encode_row(groups, Arc::new(fields_info.clone()));
-                Ok(Response::Query(QueryResponse::new(
-                    Arc::new(fields_info),
-                    result,
-                )))
+        let _query_guard = cursor
+            .context
+            .clone()
+            .and_then(|context| self.sessions.begin_query(&client.socket_addr(), context));
+
+        let mut batch_rows = 0;
+        while max_rows == 0 || batch_rows < max_rows {
+            match cursor.data_rows.next().await {
+                Some(data_row) => {
+                    client
+                        .feed(PgWireBackendMessage::DataRow(data_row?))
+                        .await?;
+                    batch_rows += 1;
+                }
+                None => break,
             }
-            None => Ok(Response::Execution(Tag::new("OK").with_rows(1))),
         }
+        cursor.sent_rows += batch_rows;
+
+        if Pin::new(&mut cursor.data_rows).peek().await.is_some() {
+            client
+                .feed(PgWireBackendMessage::PortalSuspended(PortalSuspended::new()))
+                .await?;
+            self.portal_cursors
+                .lock()
+                .unwrap()
+                .insert(portal_name, cursor);
+        } else {
+            let tag = Tag::new("SELECT").with_rows(cursor.sent_rows);
+            client
+                .feed(PgWireBackendMessage::CommandComplete(tag.into()))
+                .await?;
+        }
+
+        Ok(())
     }
 
     async fn do_describe_statement<C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         stmt: &StoredStatement<Self::Statement>,
     ) -> PgWireResult<DescribeStatementResponse>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let param_types = stmt.parameter_types.clone();
-        let statement = &stmt.statement;
-        println!("Statement {:?}, {}", param_types, statement);
+        let (_, statement) = first_statement(&stmt.statement);
+        let data_types = infer_parameter_types(statement, &stmt.parameter_types);
+        let parameter_types = data_types.iter().map(pg_type).collect::<Vec<_>>();
+
+        if statement.is_empty()
+            || parse_session_command(statement).is_some()
+            || parse_copy_query(statement).is_some()
+        {
+            return Ok(DescribeStatementResponse::new(parameter_types, vec![]));
+        }
 
-        return Err(PgWireError::IoError(Error::new(
-            ErrorKind::Other,
-            "Failed to make statement result",
-        )));
+        if parse_explain(statement).is_some() {
+            let fields_info = plan_columns().fields(&Format::UnifiedText);
+            return Ok(DescribeStatementResponse::new(
+                parameter_types,
+                fields_info.to_vec(),
+            ));
+        }
+
+        let bound = bind_placeholder_literals(&stmt.statement, &data_types)?;
+        let (offset, query) = first_statement(&bound.query);
+        if let Some(result) = self.server_result(client, query) {
+            let result = result.map_err(|err| unbound_error(err, &bound, offset))?;
+            let fields_info = result.fields(&Format::UnifiedText);
+            return Ok(DescribeStatementResponse::new(
+                parameter_types,
+                fields_info.to_vec(),
+            ));
+        }
+
+        let context = QueryContext::new(None, QueryLimits::default());
+        let groups = evaluate_with_provider(&context, query, Box::new(SchemaDataProvider))
+            .map_err(|err| unbound_error(err, &bound, offset))?;
+        let fields_info = match groups {
+            Some((groups, types)) => encode_columns(&groups, &types, &Format::UnifiedText),
+            None => vec![],
+        };
+
+        Ok(DescribeStatementResponse::new(parameter_types, fields_info))
     }
 
     async fn do_describe_portal<C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         portal: &Portal<Self::Statement>,
-    ) -> PgWireResult<DescribePortalResponse> {
-        let first_query = make_qeury(portal);
-        let query = first_query.split(';').next().unwrap();
-        if query.to_lowercase().starts_with("set") {
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let bound = bind_parameters(portal)?;
+        let (offset, query) = first_statement(&bound.query);
+        self.describe_portal_query(client, portal, query)
+            .map_err(|err| unbound_error(err, &bound, offset))
+    }
+}
+
+impl GitQLBackend {
+    /// Describes the rows of a portal running `query`, the first statement of
+    /// its bound statement.
+    fn describe_portal_query<C>(
+        &self,
+        client: &C,
+        portal: &Portal<String>,
+        query: &str,
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo,
+    {
+        if parse_session_command(query).is_some() || parse_copy_query(query).is_some() {
             return Ok(DescribePortalResponse::new(vec![]));
         }
 
-        let git_repo_result = validate_git_repositories(&self.repositories);
-        let repos = git_repo_result.ok().unwrap();
+        if parse_explain(query).is_some() {
+            let fields_info = plan_columns().fields(&portal.result_column_format);
+            return Ok(DescribePortalResponse::new(fields_info.to_vec()));
+        }
+
+        if let Some(result) = self.server_result(client, query) {
+            let fields_info = result?.fields(&portal.result_column_format);
+            return Ok(DescribePortalResponse::new(fields_info.to_vec()));
+        }
+
+        if let Some(titles) = streaming_titles(query) {
+            let fields_info = streaming_columns(&titles, &portal.result_column_format);
+            return Ok(DescribePortalResponse::new(fields_info));
+        }
 
-        let schema = Schema {
-            tables_fields_names: TABLES_FIELDS_NAMES.to_owned(),
-            tables_fields_types: TABLES_FIELDS_TYPES.to_owned(),
+        let (context, _query_guard) = self.start_query(client);
+        let groups = match self.evaluate_query(client, &context, query) {
+            Ok(groups) => groups,
+            Err(err) => {
+                self.log_query(client, query, &context, Err(&err));
+                return Err(err);
+            }
         };
 
-        let mut env = Environment::new(schema);
-        let tokenizer_result = tokenizer::tokenize(query.to_string());
-        if tokenizer_result.is_err() {
-            println!("Cannot tokenize result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                tokenizer_result.err().unwrap().message().to_owned(),
-            )));
-        }
-
-        let tokens = tokenizer_result.ok().unwrap();
-        if tokens.is_empty() {
-            println!("Empty Tokens");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                "Empty Tokens",
-            )));
-        }
-
-        let parser_result = parser::parse_gql(tokens, &mut env);
-        if parser_result.is_err() {
-            let parser_err = parser_result.err().unwrap();
-            let error_message =
-                parser_err.message().to_owned() + "\nHelp: " + &parser_err.helps().join("\n");
-            println!("Cannot parse result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                error_message,
-            )));
-        }
-
-        let query_node = parser_result.ok().unwrap();
-
-        let provider: Box<dyn DataProvider> = Box::new(GitDataProvider::new(repos.to_vec()));
-        let evaluation_result = engine::evaluate(&mut env, &provider, query_node);
-
-        if evaluation_result.is_err() {
-            println!("Cannot evaluate result");
-            return Err(PgWireError::IoError(Error::new(
-                ErrorKind::Other,
-                evaluation_result.err().unwrap(),
-            )));
-        }
-        let engine_result = evaluation_result.ok().unwrap();
-
-        if let SelectedGroups(mut groups, hidden_selection) = engine_result {
-            let mut indexes = vec![];
-            for (index, title) in groups.titles.iter().enumerate() {
-                if hidden_selection.contains(title) {
-                    indexes.insert(0, index);
-                }
+        if let Some((groups, types)) = groups {
+            let fields_info = encode_columns(&groups, &types, &portal.result_column_format);
+
+            let mut locked_cache = self.query_cache.lock().unwrap();
+            locked_cache.insert(query.to_string(), (groups, types, context));
+
+            return Ok(DescribePortalResponse::new(fields_info));
+        }
+
+        Ok(DescribePortalResponse::new(vec![]))
+    }
+
+    /// Runs one statement of a simple query. Only the last statement of a
+    /// batch is streamed, so an error in an earlier one is known before the
+    /// statements after it run.
+    fn execute_statement<'a, C>(
+        &self,
+        client: &C,
+        query: &'a str,
+        is_last: bool,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo,
+    {
+        if let Some(command) = parse_session_command(query) {
+            let (tag, transaction_status) = self.execute_session_command(client, command)?;
+            return Ok(session_response(tag, transaction_status));
+        }
+
+        if let Some(copy_query) = parse_copy_query(query) {
+            let copy_query = copy_query.map_err(|message| user_error("0A000", message))?;
+            let offset = query.find(&copy_query.query).unwrap_or(0);
+            return self
+                .copy_out(client, copy_query)
+                .map_err(|err| shifted_error(err, query, offset));
+        }
+
+        if let Some(explain_query) = parse_explain(query) {
+            let explain_query = explain_query.map_err(|message| user_error("42601", message))?;
+            let result = self
+                .explain_result(client, &explain_query)
+                .map_err(|err| inner_error(err, query, explain_query.query))?;
+            let fields_info = result.fields(&Format::UnifiedText);
+            let data_rows = result.data_rows(fields_info.clone());
+            return Ok(Response::Query(QueryResponse::new(fields_info, data_rows)));
+        }
+
+        if let Some(result) = self.server_result(client, query) {
+            let result = result?;
+            let fields_info = result.fields(&Format::UnifiedText);
+            let data_rows = result.data_rows(fields_info.clone());
+            return Ok(Response::Query(QueryResponse::new(fields_info, data_rows)));
+        }
+
+        if is_last {
+            if let Some(streamed) = self.stream_query(client, query, &Format::UnifiedText) {
+                let query_guard = self
+                    .sessions
+                    .begin_query(&client.socket_addr(), streamed.context);
+                let data_rows = guarded(streamed.data_rows, query_guard);
+                return Ok(Response::Query(QueryResponse::new(
+                    streamed.fields_info,
+                    data_rows,
+                )));
             }
+        }
 
-            if groups.len() > 1 {
-                groups.flat();
+        let (context, _query_guard) = self.start_query(client);
+        let response = match self.evaluate_query(client, &context, query) {
+            Ok(Some((groups, types))) => Ok(encode_response(
+                &groups,
+                &types,
+                &Format::UnifiedText,
+                &context,
+            )),
+            Ok(None) => Ok((0, Response::Execution(Tag::new("OK").with_rows(1)))),
+            Err(err) => Err(err),
+        };
+
+        let rows = response.as_ref().map(|(rows, _)| *rows);
+        self.log_query(client, query, &context, rows);
+        response.map(|(_, response)| response)
+    }
+
+    /// Runs the query of a COPY statement and sends its rows as copy data,
+    /// streamed as they are read when the query allows it.
+    fn copy_out<'a, C>(&self, client: &C, copy_query: CopyQuery) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo,
+    {
+        if let Some(titles) = streaming_titles(&copy_query.query) {
+            let header = copy_query.header(&titles);
+            let query = copy_query.query.clone();
+            let (context, query_guard) = self.start_query(client);
+            let rows = stream_rows(
+                query,
+                self.allowed_repositories(client),
+                context,
+                move |values| Ok(copy_query.row(values.iter().copied())),
+                self.query_recorder(client),
+            );
+            let data = guarded(
+                stream::iter(header.map(Ok)).chain(rows).boxed(),
+                query_guard,
+            );
+            return Ok(Response::CopyOut(CopyResponse::new(0, titles.len(), data)));
+        }
+
+        let query = copy_query.query.as_str();
+        let (context, _query_guard) = self.start_query(client);
+        let groups = match self.evaluate_query(client, &context, query) {
+            Ok(Some((groups, _))) => groups,
+            Ok(None) => {
+                let err = user_error("0A000", "COPY query must return rows".to_owned());
+                self.log_query(client, query, &context, Err(&err));
+                return Err(err);
             }
+            Err(err) => {
+                self.log_query(client, query, &context, Err(&err));
+                return Err(err);
+            }
+        };
 
-            for index in indexes {
-                groups.titles.remove(index);
+        let started = Instant::now();
+        let (rows, data) = copy_query.encode(&groups);
+        context.record_phase("encode", started.elapsed());
+        self.log_query(client, query, &context, Ok(rows));
 
-                for row in &mut groups.groups[0].rows {
-                    row.values.remove(index);
-                }
+        Ok(Response::CopyOut(CopyResponse::new(
+            0,
+            groups.titles.len(),
+            data,
+        )))
+    }
+
+    /// Describes the plan of an EXPLAIN statement, running the query first
+    /// when ANALYZE asks for actual timings and row counts.
+    fn explain_result<C>(&self, client: &C, explain_query: &ExplainQuery) -> PgWireResult<ResultSet>
+    where
+        C: ClientInfo,
+    {
+        let query = explain_query.query;
+        let repositories = self.allowed_repositories(client);
+        let repos =
+            validate_git_repositories(&repositories).map_err(|err| user_error("XX000", err))?;
+
+        let (context, _query_guard) = self.start_query(client);
+        let started = Instant::now();
+        let query_node = parse_query(&mut query_environment(), &context, query)?;
+        let planning = started.elapsed();
+
+        if !explain_query.analyze {
+            return Ok(explain(&query_node, &repos, None));
+        }
+
+        let started = Instant::now();
+        let rows = match self.evaluate_query(client, &context, query) {
+            Ok(groups) => groups.map_or(0, |(groups, _)| {
+                groups.groups.iter().map(|group| group.rows.len()).sum()
+            }),
+            Err(err) => {
+                self.log_query(client, query, &context, Err(&err));
+                return Err(err);
             }
+        };
+        let execution = started.elapsed();
+        self.log_query(client, query, &context, Ok(rows));
 
-            let mut fields_info: Vec<FieldInfo> = vec![];
+        let analysis = Analysis {
+            stats: context.stats(),
+            rows,
+            planning,
+            execution,
+        };
+        Ok(explain(&query_node, &repos, Some(&analysis)))
+    }
+
+    fn execute_portal<C>(&self, client: &C, portal: &Portal<String>) -> PgWireResult<PortalResult>
+    where
+        C: ClientInfo,
+    {
+        let bound = bind_parameters(portal)?;
+        let (offset, query) = first_statement(&bound.query);
+        self.execute_portal_query(client, portal, query)
+            .map_err(|err| unbound_error(err, &bound, offset))
+    }
+
+    /// Runs `query`, the first statement of the bound statement of a portal.
+    fn execute_portal_query<C>(
+        &self,
+        client: &C,
+        portal: &Portal<String>,
+        query: &str,
+    ) -> PgWireResult<PortalResult>
+    where
+        C: ClientInfo,
+    {
+        if let Some(command) = parse_session_command(query) {
+            let (tag, transaction_status) = self.execute_session_command(client, command)?;
+            return Ok(PortalResult::Session(tag, transaction_status));
+        }
 
-            for (index, title) in groups.titles.iter().enumerate() {
-                let field_result = encode_column(title, index);
-                if field_result.is_err() {
-                    continue;
+        if parse_copy_query(query).is_some() {
+            return Err(user_error(
+                "0A000",
+                "COPY is only supported through the simple query protocol".to_owned(),
+            ));
+        }
+
+        if let Some(explain_query) = parse_explain(query) {
+            let explain_query = explain_query.map_err(|message| user_error("42601", message))?;
+            let result = self
+                .explain_result(client, &explain_query)
+                .map_err(|err| inner_error(err, query, explain_query.query))?;
+            let fields_info = result.fields(&portal.result_column_format);
+            let data_rows = result.data_rows(fields_info.clone());
+            return Ok(PortalResult::Rows(fields_info, data_rows));
+        }
+
+        if let Some(result) = self.server_result(client, query) {
+            let result = result?;
+            let fields_info = result.fields(&portal.result_column_format);
+            let data_rows = result.data_rows(fields_info.clone());
+            return Ok(PortalResult::Rows(fields_info, data_rows));
+        }
+
+        let cached = self.query_cache.lock().unwrap().remove(query);
+        let (groups, types, context) = match cached {
+            Some(cached) => {
+                metrics::QUERY_CACHE.with_label_values(&["hit"]).inc();
+                cached
+            }
+            None => {
+                metrics::QUERY_CACHE.with_label_values(&["miss"]).inc();
+                if let Some(streamed) =
+                    self.stream_query(client, query, &portal.result_column_format)
+                {
+                    return Ok(PortalResult::Streamed(streamed));
+                }
+
+                let (context, _query_guard) = self.start_query(client);
+                match self.evaluate_query(client, &context, query) {
+                    Ok(Some((groups, types))) => (groups, types, context),
+                    Ok(None) => return Ok(PortalResult::Execution(Tag::new("OK").with_rows(1))),
+                    Err(err) => {
+                        self.log_query(client, query, &context, Err(&err));
+                        return Err(err);
+                    }
                 }
-                fields_info.push(field_result.ok().unwrap());
             }
+        };
 
-            let mut locked_cache = self.query_cache.lock().unwrap();
-            locked_cache.insert(query.to_string(), groups);
+        let started = Instant::now();
+        let fields_info = Arc::new(encode_columns(
+            &groups,
+            &types,
+            &portal.result_column_format,
+        ));
+        let rows = groups.groups.iter().map(|group| group.rows.len()).sum();
+        let data_rows = encode_row(&groups, fields_info.clone()).boxed();
+        context.record_phase("encode", started.elapsed());
+        self.log_query(client, query, &context, Ok(rows));
 
-            return Ok(DescribePortalResponse::new(fields_info));
+        Ok(PortalResult::Rows(fields_info, data_rows))
+    }
+
+    /// Starts streaming `query` when its rows need no sorting or grouping, so
+    /// the first rows reach the client while the repositories are still walked.
+    /// The caller marks the session busy while it reads the rows.
+    fn stream_query<C>(&self, client: &C, query: &str, format: &Format) -> Option<StreamedRows>
+    where
+        C: ClientInfo,
+    {
+        let titles = streaming_titles(query)?;
+        let fields_info = Arc::new(streaming_columns(&titles, format));
+        let context = self.query_context(client);
+        let row_fields = fields_info.clone();
+        let data_rows = stream_rows(
+            query.to_owned(),
+            self.allowed_repositories(client),
+            context.clone(),
+            move |values| encode_values(values.iter().copied(), row_fields.clone()),
+            self.query_recorder(client),
+        );
+        Some(StreamedRows {
+            fields_info,
+            data_rows,
+            context,
+        })
+    }
+
+    fn start_query<C>(&self, client: &C) -> (Arc<QueryContext>, Option<QueryGuard>)
+    where
+        C: ClientInfo,
+    {
+        let context = self.query_context(client);
+        let query_guard = self
+            .sessions
+            .begin_query(&client.socket_addr(), context.clone());
+        (context, query_guard)
+    }
+
+    fn query_context<C>(&self, client: &C) -> Arc<QueryContext>
+    where
+        C: ClientInfo,
+    {
+        Arc::new(QueryContext::new(
+            *self.statement_timeout.lock().unwrap(),
+            self.query_limits.for_user(self.session_user(client)),
+        ))
+    }
+
+    fn log_query<C>(
+        &self,
+        client: &C,
+        query: &str,
+        context: &QueryContext,
+        result: Result<usize, &PgWireError>,
+    ) where
+        C: ClientInfo,
+    {
+        self.query_recorder(client).record(query, context, result);
+    }
+
+    fn query_recorder<C>(&self, client: &C) -> QueryRecorder
+    where
+        C: ClientInfo,
+    {
+        let metadata = client.metadata();
+        let addr = client.socket_addr();
+        QueryRecorder {
+            session_id: self
+                .sessions
+                .session(&client.socket_addr())
+                .map(|session| session.pid),
+            user: self.session_user(client).to_owned(),
+            client_addr: if is_local_addr(&addr) {
+                "[local]".to_owned()
+            } else {
+                addr.to_string()
+            },
+            database: metadata.get(METADATA_DATABASE).cloned(),
+            application_name: metadata.get("application_name").cloned(),
+            audit_log: self.audit_log.clone(),
+        }
+    }
+
+    fn session_user<'c, C>(&self, client: &'c C) -> &'c str
+    where
+        C: ClientInfo,
+    {
+        client
+            .metadata()
+            .get(METADATA_USER)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Applies a session command, returning its tag and the transaction status
+    /// it moves the connection to, if any.
+    fn execute_session_command<C>(
+        &self,
+        client: &C,
+        command: Result<SessionCommand, String>,
+    ) -> PgWireResult<(Tag, Option<TransactionStatus>)>
+    where
+        C: ClientInfo,
+    {
+        let command = command.map_err(|message| user_error("42601", message))?;
+        let tag = Tag::new(command.tag());
+        let reported = self.settings(client).reported();
+
+        match command {
+            SessionCommand::Begin => return Ok((tag, Some(TransactionStatus::Transaction))),
+            SessionCommand::Commit | SessionCommand::Rollback => {
+                return Ok((tag, Some(TransactionStatus::Idle)))
+            }
+            SessionCommand::Set(name, value) => self.set_parameter(&name, value)?,
+            SessionCommand::Reset(Some(name)) => self.set_parameter(&name, None)?,
+            SessionCommand::Reset(None) => self.reset_parameters(),
+            SessionCommand::DiscardAll => {
+                self.reset_parameters();
+                self.portal_cursors.lock().unwrap().clear();
+                self.query_cache.lock().unwrap().clear();
+                self.client_releases
+                    .lock()
+                    .unwrap()
+                    .extend([ClientRelease::Statement(None), ClientRelease::Portal(None)]);
+            }
+            SessionCommand::Close(Some(name)) => {
+                self.portal_cursors.lock().unwrap().remove(&name);
+                let release = ClientRelease::Portal(Some(name));
+                self.client_releases.lock().unwrap().push(release);
+            }
+            SessionCommand::Close(None) => {
+                self.portal_cursors.lock().unwrap().clear();
+                let release = ClientRelease::Portal(None);
+                self.client_releases.lock().unwrap().push(release);
+            }
+            SessionCommand::Deallocate(name) => {
+                let release = ClientRelease::Statement(name);
+                self.client_releases.lock().unwrap().push(release);
+            }
+            SessionCommand::SetTransaction => {}
+        }
+
+        let changed = self
+            .settings(client)
+            .reported()
+            .into_iter()
+            .filter(|setting| !reported.contains(setting));
+        self.parameter_reports.lock().unwrap().extend(changed);
+        Ok((tag, None))
+    }
+
+    /// Takes the reported settings changed since the last call, to be sent to
+    /// the client with ParameterStatus.
+    pub fn take_parameter_reports(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.parameter_reports.lock().unwrap())
+    }
+
+    /// Takes the prepared statements and portals released since the last
+    /// call, to be removed from the client's stores.
+    pub fn take_client_releases(&self) -> Vec<ClientRelease> {
+        std::mem::take(&mut *self.client_releases.lock().unwrap())
+    }
+
+    /// Drops the suspended rows of portal `name`, closed by the client.
+    pub fn close_cursor(&self, name: &str) {
+        self.portal_cursors.lock().unwrap().remove(name);
+    }
+
+    /// Drops every suspended portal at the Sync ending an implicit
+    /// transaction, and releases the portals too as PostgreSQL destroys them
+    /// there.
+    pub fn close_cursors(&self) {
+        let releases = self
+            .portal_cursors
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(name, _)| ClientRelease::Portal(Some(name)))
+            .collect::<Vec<_>>();
+        self.client_releases.lock().unwrap().extend(releases);
+    }
+
+    /// Sets a session parameter, or restores its default when `value` is `None`.
+    fn set_parameter(&self, name: &str, mut value: Option<String>) -> PgWireResult<()> {
+        if name == "client_encoding" {
+            if let Some(value) = value.as_deref().filter(|value| !is_utf8_encoding(value)) {
+                return Err(user_error(
+                    "22023",
+                    format!(
+                        "invalid value for parameter \"client_encoding\": \"{}\"",
+                        value
+                    ),
+                ));
+            }
+            value = value.map(|_| "UTF8".to_owned());
+        }
+
+        if name == "statement_timeout" {
+            let timeout = match value {
+                Some(value) => parse_statement_timeout(&value)
+                    .map_err(|message| user_error("22023", message))?,
+                None => self.default_statement_timeout,
+            };
+            *self.statement_timeout.lock().unwrap() = timeout;
+            return Ok(());
+        }
+
+        let mut parameters = self.session_parameters.lock().unwrap();
+        match value {
+            Some(value) => parameters.insert(name.to_owned(), value),
+            None => parameters.remove(name),
+        };
+        Ok(())
+    }
+
+    fn reset_parameters(&self) {
+        self.session_parameters.lock().unwrap().clear();
+        *self.statement_timeout.lock().unwrap() = self.default_statement_timeout;
+    }
+
+    /// Answers settings and catalog queries, which are computed by the server
+    /// instead of the GitQL engine.
+    fn server_result<C>(&self, client: &C, query: &str) -> Option<PgWireResult<ResultSet>>
+    where
+        C: ClientInfo,
+    {
+        if let Some(settings_query) = parse_settings_query(query) {
+            let result = self
+                .settings(client)
+                .evaluate(&settings_query)
+                .map_err(|message| user_error("42704", message));
+            return Some(result);
+        }
+
+        let user = self.session_user(client).to_owned();
+        let session = CatalogSession {
+            database: client
+                .metadata()
+                .get(METADATA_DATABASE)
+                .cloned()
+                .unwrap_or_else(|| user.clone()),
+            user,
+            settings: self.settings(client).all(),
+        };
+        catalog_query(query, &session)
+            .map(|result| result.map_err(|message| user_error("0A000", message)))
+    }
+
+    fn settings<C>(&self, client: &C) -> Settings
+    where
+        C: ClientInfo,
+    {
+        Settings {
+            server: self.server_parameters.clone(),
+            session: self.session_parameters.lock().unwrap().clone(),
+            statement_timeout: *self.statement_timeout.lock().unwrap(),
+            user: self.session_user(client).to_owned(),
+            application_name: client.metadata().get("application_name").cloned(),
+        }
+    }
+
+    fn allowed_repositories<C>(&self, client: &C) -> Vec<String>
+    where
+        C: ClientInfo,
+    {
+        self.acl
+            .allowed_repositories(self.session_user(client), &self.repositories)
+    }
+
+    fn evaluate_query<C>(
+        &self,
+        client: &C,
+        context: &Arc<QueryContext>,
+        query: &str,
+    ) -> PgWireResult<Option<(GitQLObject, ColumnTypes)>>
+    where
+        C: ClientInfo,
+    {
+        let repositories = self.allowed_repositories(client);
+        let repos =
+            validate_git_repositories(&repositories).map_err(|err| user_error("XX000", err))?;
+
+        let provider: Box<dyn DataProvider> =
+            Box::new(GitDataProvider::new(repos, context.clone()));
+        evaluate_with_provider(context, query, provider)
+    }
+}
+
+fn evaluate_with_provider(
+    context: &QueryContext,
+    query: &str,
+    provider: Box<dyn DataProvider>,
+) -> PgWireResult<Option<(GitQLObject, ColumnTypes)>> {
+    let mut env = query_environment();
+    let query_node = parse_query(&mut env, context, query)?;
+    let types = column_types(&env, &query_node);
+
+    let started = Instant::now();
+    let evaluation_result = engine::evaluate(&mut env, &provider, query_node);
+    context.record_phase("evaluate", started.elapsed());
+
+    if let Some(interruption) = context.interruption() {
+        return Err(interruption_error(interruption));
+    }
+
+    if evaluation_result.is_err() {
+        return Err(user_error("XX000", evaluation_result.err().unwrap()));
+    }
+    let engine_result = evaluation_result.ok().unwrap();
+
+    if let SelectedGroups(mut groups, hidden_selection) = engine_result {
+        let mut indexes = vec![];
+        for (index, title) in groups.titles.iter().enumerate() {
+            if hidden_selection.contains(title) {
+                indexes.insert(0, index);
+            }
         }
 
-        return Ok(DescribePortalResponse::new(vec![]));
+        if groups.len() > 1 {
+            groups.flat();
+        }
+
+        for index in indexes {
+            groups.titles.remove(index);
+
+            for row in &mut groups.groups[0].rows {
+                row.values.remove(index);
+            }
+        }
+
+        let rows = groups.groups.iter().map(|group| group.rows.len()).sum();
+        if context.check_result_rows(rows).is_err() {
+            return Err(interruption_error(context.interruption().unwrap()));
+        }
+
+        return Ok(Some((groups, types)));
     }
+
+    Ok(None)
+}
+
+fn query_environment() -> Environment {
+    let schema = Schema {
+        tables_fields_names: TABLES_FIELDS_NAMES.to_owned(),
+        tables_fields_types: TABLES_FIELDS_TYPES.to_owned(),
+    };
+
+    Environment::new(schema)
+}
+
+fn parse_query(env: &mut Environment, context: &QueryContext, query: &str) -> PgWireResult<Query> {
+    let started = Instant::now();
+    let tokenizer_result = tokenizer::tokenize(query.to_string());
+    context.record_phase("tokenize", started.elapsed());
+    if tokenizer_result.is_err() {
+        return Err(diagnostic_error(&tokenizer_result.err().unwrap()));
+    }
+
+    let tokens = tokenizer_result.ok().unwrap();
+    if tokens.is_empty() {
+        return Err(user_error("42601", "Empty Tokens".to_owned()));
+    }
+
+    let started = Instant::now();
+    let parser_result = parser::parse_gql(tokens, env);
+    context.record_phase("parse", started.elapsed());
+    if parser_result.is_err() {
+        return Err(diagnostic_error(&parser_result.err().unwrap()));
+    }
+
+    Ok(parser_result.ok().unwrap())
+}
+
+enum PortalResult {
+    Execution(Tag),
+    Session(Tag, Option<TransactionStatus>),
+    Rows(
+        Arc<Vec<FieldInfo>>,
+        BoxStream<'static, PgWireResult<DataRow>>,
+    ),
+    Streamed(StreamedRows),
+}
+
+/// Rows of a streamed query, read from the repositories as they are sent.
+struct StreamedRows {
+    fields_info: Arc<Vec<FieldInfo>>,
+    data_rows: BoxStream<'static, PgWireResult<DataRow>>,
+    context: Arc<QueryContext>,
+}
+
+/// Keeps `query_guard` until `data_rows` is dropped, so the session stays
+/// busy while the rows are read.
+fn guarded<T>(
+    data_rows: BoxStream<'static, T>,
+    query_guard: Option<QueryGuard>,
+) -> BoxStream<'static, T>
+where
+    T: Send + 'static,
+{
+    data_rows
+        .map(move |row| {
+            let _query_guard = &query_guard;
+            row
+        })
+        .boxed()
+}
+
+/// A prepared statement or portal released by DEALLOCATE, CLOSE, DISCARD ALL
+/// or the Sync ending a suspended portal, where `None` stands for all of them.
+pub enum ClientRelease {
+    Statement(Option<String>),
+    Portal(Option<String>),
+}
+
+/// Rows of a portal suspended by Execute with a row limit, resumed by the
+/// next Execute of the same portal. The session is only marked busy while an
+/// Execute reads the rows of a streamed query, whose context is kept here.
+struct PortalCursor {
+    portal: Arc<Portal<String>>,
+    data_rows: Peekable<BoxStream<'static, PgWireResult<DataRow>>>,
+    sent_rows: usize,
+    context: Option<Arc<QueryContext>>,
+}
+
+const UNRESOLVED_TABLE_MESSAGE: &str = "Unresolved table name";
+const UNRESOLVED_FIELD_MESSAGE: &str = "No field with name `";
+const TABLE_FIELD_MESSAGE: &str = " has no field with name `";
+
+fn interruption_error(interruption: Interruption) -> PgWireError {
+    user_error(interruption.code, interruption.message)
+}
+
+fn user_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
+
+/// Converts a tokenizer or parser diagnostic to an error response, with the
+/// diagnostic location as the 1-based position and its helps as the hint.
+fn diagnostic_error(diagnostic: &Diagnostic) -> PgWireError {
+    let mut info = ErrorInfo::new(
+        "ERROR".to_owned(),
+        diagnostic_code(diagnostic.message()).to_owned(),
+        diagnostic.message().to_owned(),
+    );
+    if let Some((start, _)) = diagnostic.location() {
+        info.position = Some((start + 1).to_string());
+    }
+    if !diagnostic.helps().is_empty() {
+        info.hint = Some(diagnostic.helps().join("\n"));
+    }
+    PgWireError::UserError(Box::new(info))
+}
+
+/// Moves the position of an error in the statement found `offset` bytes into
+/// `text` to its position in `text`.
+fn shifted_error(err: PgWireError, text: &str, offset: usize) -> PgWireError {
+    let shift = text[..offset].chars().count();
+    map_error_position(err, |position| position + shift)
+}
+
+/// Moves the position of an error in the query of an EXPLAIN statement to
+/// its position in the statement.
+fn inner_error(err: PgWireError, statement: &str, query: &str) -> PgWireError {
+    shifted_error(err, statement, statement.find(query).unwrap_or(0))
+}
+
+/// Moves the position of an error in the statement found `offset` bytes into
+/// a bound statement to its position in the statement the client sent.
+fn unbound_error(err: PgWireError, bound: &BoundStatement, offset: usize) -> PgWireError {
+    let shift = bound.query[..offset].chars().count();
+    map_error_position(err, |position| bound.unbound_position(position + shift))
+}
+
+fn map_error_position<F>(err: PgWireError, map: F) -> PgWireError
+where
+    F: FnOnce(usize) -> usize,
+{
+    match err {
+        PgWireError::UserError(mut info) => {
+            let position = info.position.as_ref().and_then(|p| p.parse::<usize>().ok());
+            if let Some(position) = position {
+                info.position = Some(map(position).to_string());
+            }
+            PgWireError::UserError(info)
+        }
+        err => err,
+    }
+}
+
+/// Reports names the parser could not resolve as undefined tables or columns
+/// and everything else as a syntax error. The messages are the ones GitQL
+/// reports for unknown table names and for fields no table has.
+fn diagnostic_code(message: &str) -> &'static str {
+    if message.starts_with(UNRESOLVED_TABLE_MESSAGE) {
+        "42P01"
+    } else if message.starts_with(UNRESOLVED_FIELD_MESSAGE)
+        || (message.starts_with("Table ") && message.contains(TABLE_FIELD_MESSAGE))
+    {
+        "42703"
+    } else {
+        "42601"
+    }
+}
+
+/// Tells whether `encoding` names UTF8, the only client encoding supported as
+/// GitQL values are Rust strings.
+fn is_utf8_encoding(encoding: &str) -> bool {
+    ["utf8", "utf-8", "unicode"]
+        .iter()
+        .any(|name| encoding.eq_ignore_ascii_case(name))
+}
+
+fn session_response<'a>(tag: Tag, transaction_status: Option<TransactionStatus>) -> Response<'a> {
+    match transaction_status {
+        Some(TransactionStatus::Transaction) => Response::TransactionStart(tag),
+        Some(_) => Response::TransactionEnd(tag),
+        None => Response::Execution(tag),
+    }
+}
+
+fn encode_response<'a>(
+    groups: &GitQLObject,
+    types: &ColumnTypes,
+    format: &Format,
+    context: &QueryContext,
+) -> (usize, Response<'a>) {
+    let started = Instant::now();
+    let fields_info = encode_columns(groups, types, format);
+    let rows = groups.groups.iter().map(|group| group.rows.len()).sum();
+    let result = encode_row(groups, Arc::new(fields_info.clone()));
+    context.record_phase("encode", started.elapsed());
+
+    (
+        rows,
+        Response::Query(QueryResponse::new(Arc::new(fields_info), result)),
+    )
+}
+
+fn streaming_columns(titles: &[String], format: &Format) -> Vec<FieldInfo> {
+    titles
+        .iter()
+        .enumerate()
+        .map(|(index, title)| encode_column(title, None, index, format))
+        .collect()
+}
+
+fn encode_columns(groups: &GitQLObject, types: &ColumnTypes, format: &Format) -> Vec<FieldInfo> {
+    groups
+        .titles
+        .iter()
+        .enumerate()
+        .map(|(index, title)| encode_column(title, types.get(title), index, format))
+        .collect()
 }
 
 pub struct MakeGitQLBackend {
     repositories: Arc<[String]>,
+    acl: Arc<RepositoryAcl>,
+    sessions: Arc<SessionRegistry>,
     query_parser: Arc<NoopQueryParser>,
+    statement_timeout: Option<Duration>,
+    query_limits: Arc<QueryLimitsConfig>,
+    audit_log: Option<Arc<AuditLog>>,
+    server_parameters: Arc<DefaultServerParameterProvider>,
 }
 
 impl MakeGitQLBackend {
-    pub fn new(path: Option<String>) -> MakeGitQLBackend {
-        let entries = fs::read_dir(path.as_deref().unwrap_or("."))
-            .unwrap()
+    pub fn new(
+        roots: &[String],
+        acl: Arc<RepositoryAcl>,
+        sessions: Arc<SessionRegistry>,
+        statement_timeout: Option<Duration>,
+        query_limits: Arc<QueryLimitsConfig>,
+        audit_log: Option<Arc<AuditLog>>,
+        server_parameters: Arc<DefaultServerParameterProvider>,
+    ) -> MakeGitQLBackend {
+        let entries = roots
+            .iter()
+            .filter_map(|root| fs::read_dir(root).ok())
+            .flat_map(|entries| entries.flatten())
             .filter_map(|entry| {
-                let entry = entry.ok().unwrap();
                 let path = entry.path();
                 if path.is_dir() {
                     return path.into_os_string().into_string().ok();
@@ -345,7 +1229,13 @@ impl MakeGitQLBackend {
 
         MakeGitQLBackend {
             repositories: Arc::from(entries),
+            acl,
+            sessions,
             query_parser: Arc::new(NoopQueryParser::new()),
+            statement_timeout,
+            query_limits,
+            audit_log,
+            server_parameters,
         }
     }
 }
@@ -356,21 +1246,86 @@ impl MakeHandler for MakeGitQLBackend {
     fn make(&self) -> Self::Handler {
         Arc::new(GitQLBackend {
             repositories: self.repositories.clone(),
+            acl: self.acl.clone(),
+            sessions: self.sessions.clone(),
             query_parser: self.query_parser.clone(),
             query_cache: Arc::new(Mutex::new(HashMap::new())),
+            query_limits: self.query_limits.clone(),
+            audit_log: self.audit_log.clone(),
+            default_statement_timeout: self.statement_timeout,
+            statement_timeout: Mutex::new(self.statement_timeout),
+            portal_cursors: Mutex::new(HashMap::new()),
+            session_parameters: Mutex::new(HashMap::new()),
+            parameter_reports: Mutex::new(vec![]),
+            client_releases: Mutex::new(vec![]),
+            server_parameters: self.server_parameters.clone(),
         })
     }
 }
 
-fn validate_git_repositories(repositories: &Arc<[String]>) -> Result<Vec<gix::Repository>, String> {
+fn validate_git_repositories(repositories: &[String]) -> Result<Vec<gix::Repository>, String> {
     let mut git_repositories: Vec<gix::Repository> = vec![];
     for repository in repositories.iter() {
         let git_repository = gix::open(repository);
         if git_repository.is_err() {
-            println!("This is not git repository");
+            tracing::debug!(
+                repository = repository.as_str(),
+                "skipping non git directory"
+            );
             continue;
         }
         git_repositories.push(git_repository.ok().unwrap());
     }
     Ok(git_repositories)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_code_reports_unresolved_tables() {
+        assert_eq!(diagnostic_code("Unresolved table name"), "42P01");
+    }
+
+    #[test]
+    fn diagnostic_code_reports_unresolved_fields() {
+        assert_eq!(diagnostic_code("No field with name `titel`"), "42703");
+        assert_eq!(
+            diagnostic_code("Table commits has no field with name `titel`"),
+            "42703"
+        );
+    }
+
+    #[test]
+    fn diagnostic_code_reports_other_messages_as_syntax_errors() {
+        assert_eq!(diagnostic_code("Expect `FROM` keyword"), "42601");
+        assert_eq!(diagnostic_code("Table name must be an identifier"), "42601");
+    }
+
+    #[test]
+    fn only_utf8_client_encodings_are_accepted() {
+        for encoding in ["UTF8", "utf-8", "Unicode"] {
+            assert!(is_utf8_encoding(encoding), "{}", encoding);
+        }
+        for encoding in ["LATIN1", "SQL_ASCII", "UTF8, LATIN1", ""] {
+            assert!(!is_utf8_encoding(encoding), "{}", encoding);
+        }
+    }
+
+    #[test]
+    fn error_positions_move_to_the_statement_the_client_sent() {
+        let err = user_error("42601", "syntax error".to_owned());
+        let err = map_error_position(err, |position| position + 1);
+        assert!(matches!(&err, PgWireError::UserError(info) if info.position.is_none()));
+
+        let text = "SELECT 1; SELECT é FROM";
+        let offset = text.find("SELECT é").unwrap();
+        let mut info = ErrorInfo::new("ERROR".to_owned(), "42601".to_owned(), String::new());
+        info.position = Some("8".to_owned());
+        match shifted_error(PgWireError::UserError(Box::new(info)), text, offset) {
+            PgWireError::UserError(info) => assert_eq!(info.position.as_deref(), Some("18")),
+            _ => panic!("expected a user error"),
+        }
+    }
+}
@@ -1,30 +1,435 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
 use pgwire::api::portal::Portal;
+use pgwire::api::results::FieldFormat;
+use pgwire::api::Type;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
 
-pub fn make_qeury(portal: &Portal<String>) -> String {
-    let mut result_query = String::new();
-    let input_query = &portal.statement.statement;
-
-    for token in input_query.split(' ') {
-        if let Some(first) = token.chars().nth(0) {
-            match first {
-                '$' => {
-                    let i = &token[1..].parse::<usize>().unwrap();
-                    match get_param(portal, *i - 1) {
-                        Some(param) => result_query.push_str(&param),
-                        None => result_query.push_str(""),
-                    }
+use crate::git_backend::describe::{infer_parameter_types, pg_type};
+
+/// A statement whose placeholders were replaced with literals.
+pub struct BoundStatement {
+    pub query: String,
+    /// Character offset and length of every placeholder in the statement,
+    /// with the character length of the literal that replaced it.
+    substitutions: Vec<(usize, usize, usize)>,
+}
+
+impl BoundStatement {
+    /// Translates a 1-based character position in `query` to the statement it
+    /// was bound from. A position inside a literal points at its placeholder.
+    pub fn unbound_position(&self, position: usize) -> usize {
+        let bound = position.saturating_sub(1) as isize;
+        let mut shift = 0;
+        for &(start, placeholder_len, literal_len) in &self.substitutions {
+            let literal_start = start as isize + shift;
+            if bound < literal_start {
+                break;
+            }
+            if bound < literal_start + literal_len as isize {
+                return start + 1;
+            }
+            shift += literal_len as isize - placeholder_len as isize;
+        }
+        (bound - shift) as usize + 1
+    }
+}
+
+/// Substitutes every `$n` placeholder outside of string literals with a GitQL
+/// literal of the bound value.
+pub fn bind_parameters(portal: &Portal<String>) -> PgWireResult<BoundStatement> {
+    let statement = &portal.statement.statement;
+    let inferred = infer_parameter_types(statement, &portal.statement.parameter_types);
+
+    substitute_placeholders(statement, |index| {
+        if index >= portal.parameters.len() {
+            return Err(bind_error(
+                "42P02",
+                format!("there is no parameter ${}", index + 1),
+            ));
+        }
+
+        let pg_type = match portal.statement.parameter_types.get(index) {
+            Some(pg_type) if *pg_type != Type::UNKNOWN => pg_type.clone(),
+            _ => inferred.get(index).map(pg_type).unwrap_or(Type::TEXT),
+        };
+        parameter_literal(portal, index, &pg_type)
+    })
+}
+
+/// Replaces every `$n` placeholder outside of string literals with the
+/// literal `literal` returns for its 0-based index.
+pub fn substitute_placeholders<F>(statement: &str, mut literal: F) -> PgWireResult<BoundStatement>
+where
+    F: FnMut(usize) -> PgWireResult<String>,
+{
+    let mut query = String::with_capacity(statement.len());
+    let mut substitutions = vec![];
+    let mut chars = statement.chars().enumerate().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == '\'' || c == '"' {
+            query.push(c);
+            for (_, next) in chars.by_ref() {
+                query.push(next);
+                if next == c {
+                    break;
                 }
-                _ => result_query.push_str(token),
             }
+            continue;
+        }
+
+        if c != '$'
+            || !chars
+                .peek()
+                .map_or(false, |(_, next)| next.is_ascii_digit())
+        {
+            query.push(c);
+            continue;
+        }
 
-            result_query.push_str(" ")
+        let mut digits = String::new();
+        while let Some((_, digit)) = chars.peek().filter(|(_, next)| next.is_ascii_digit()) {
+            digits.push(*digit);
+            chars.next();
         }
+
+        let index = digits
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index > 0)
+            .ok_or_else(|| bind_error("42P02", format!("there is no parameter ${}", digits)))?
+            - 1;
+        let value = literal(index)?;
+        substitutions.push((start, digits.len() + 1, value.chars().count()));
+        query.push_str(&value);
+    }
+
+    Ok(BoundStatement {
+        query,
+        substitutions,
+    })
+}
+
+fn parameter_literal(
+    portal: &Portal<String>,
+    index: usize,
+    pg_type: &Type,
+) -> PgWireResult<String> {
+    let value = match &portal.parameters[index] {
+        Some(value) => value,
+        None => return Ok("null".to_owned()),
+    };
+
+    if matches!(
+        portal.parameter_format.format_for(index),
+        FieldFormat::Binary
+    ) {
+        return binary_literal(index, value, pg_type);
+    }
+
+    let text =
+        std::str::from_utf8(value).map_err(|_| invalid_parameter(index, "is not valid UTF-8"))?;
+    text_literal(index, text, pg_type)
+}
+
+fn text_literal(index: usize, text: &str, pg_type: &Type) -> PgWireResult<String> {
+    match *pg_type {
+        Type::INT2 | Type::INT4 | Type::INT8 => text
+            .trim()
+            .parse::<i64>()
+            .map(|value| value.to_string())
+            .map_err(|_| invalid_parameter(index, "is not a valid integer")),
+        Type::FLOAT4 | Type::FLOAT8 | Type::NUMERIC => text
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| invalid_parameter(index, "is not a valid number"))
+            .and_then(|value| float_literal(index, value)),
+        Type::BOOL => match text.trim().to_lowercase().as_str() {
+            "t" | "true" | "y" | "yes" | "on" | "1" => Ok("true".to_owned()),
+            "f" | "false" | "n" | "no" | "off" | "0" => Ok("false".to_owned()),
+            _ => Err(invalid_parameter(index, "is not a valid boolean")),
+        },
+        Type::TIMESTAMP | Type::TIMESTAMPTZ => {
+            let text = text.trim();
+            let timestamp = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+                .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f"))
+                .or_else(|_| {
+                    chrono::DateTime::parse_from_rfc3339(text).map(|time| time.naive_utc())
+                })
+                .map_err(|_| invalid_parameter(index, "is not a valid timestamp"))?;
+            string_literal(index, &timestamp.format("%Y-%m-%d %H:%M:%S").to_string())
+        }
+        Type::DATE => {
+            let date = NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d")
+                .map_err(|_| invalid_parameter(index, "is not a valid date"))?;
+            string_literal(index, &date.format("%Y-%m-%d").to_string())
+        }
+        Type::TIME => {
+            let time = NaiveTime::parse_from_str(text.trim(), "%H:%M:%S%.f")
+                .map_err(|_| invalid_parameter(index, "is not a valid time"))?;
+            string_literal(index, &time.format("%H:%M:%S").to_string())
+        }
+        _ => string_literal(index, text),
+    }
+}
+
+fn binary_literal(index: usize, value: &[u8], pg_type: &Type) -> PgWireResult<String> {
+    match *pg_type {
+        Type::INT2 => Ok(i16::from_be_bytes(fixed_bytes(index, value)?).to_string()),
+        Type::INT4 => Ok(i32::from_be_bytes(fixed_bytes(index, value)?).to_string()),
+        Type::INT8 => Ok(i64::from_be_bytes(fixed_bytes(index, value)?).to_string()),
+        Type::FLOAT4 => float_literal(
+            index,
+            f64::from(f32::from_be_bytes(fixed_bytes(index, value)?)),
+        ),
+        Type::FLOAT8 => float_literal(index, f64::from_be_bytes(fixed_bytes(index, value)?)),
+        Type::BOOL => Ok((fixed_bytes::<1>(index, value)?[0] != 0).to_string()),
+        Type::TIMESTAMP | Type::TIMESTAMPTZ => {
+            let micros = i64::from_be_bytes(fixed_bytes(index, value)?);
+            let timestamp = postgres_epoch()
+                .checked_add_signed(Duration::microseconds(micros))
+                .ok_or_else(|| invalid_parameter(index, "is out of range"))?;
+            string_literal(index, &timestamp.format("%Y-%m-%d %H:%M:%S").to_string())
+        }
+        Type::DATE => {
+            let days = i32::from_be_bytes(fixed_bytes(index, value)?);
+            let date = postgres_epoch()
+                .date()
+                .checked_add_signed(Duration::days(i64::from(days)))
+                .ok_or_else(|| invalid_parameter(index, "is out of range"))?;
+            string_literal(index, &date.format("%Y-%m-%d").to_string())
+        }
+        Type::TIME => {
+            let micros = i64::from_be_bytes(fixed_bytes(index, value)?);
+            let time = NaiveTime::MIN
+                .overflowing_add_signed(Duration::microseconds(micros))
+                .0;
+            string_literal(index, &time.format("%H:%M:%S").to_string())
+        }
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+            let text = std::str::from_utf8(value)
+                .map_err(|_| invalid_parameter(index, "is not valid UTF-8"))?;
+            string_literal(index, text)
+        }
+        _ => Err(bind_error(
+            "0A000",
+            format!(
+                "binary format is not supported for parameter ${} of type {}",
+                index + 1,
+                pg_type.name()
+            ),
+        )),
+    }
+}
+
+fn fixed_bytes<const N: usize>(index: usize, value: &[u8]) -> PgWireResult<[u8; N]> {
+    value
+        .try_into()
+        .map_err(|_| invalid_parameter(index, "has an invalid binary representation"))
+}
+
+fn float_literal(index: usize, value: f64) -> PgWireResult<String> {
+    if !value.is_finite() {
+        return Err(invalid_parameter(index, "is not a finite number"));
+    }
+    Ok(format!("{:?}", value))
+}
+
+/// GitQL string literals have no escape sequences, so the value is wrapped in
+/// whichever quote character it does not contain.
+fn string_literal(index: usize, value: &str) -> PgWireResult<String> {
+    if !value.contains('"') {
+        return Ok(format!("\"{}\"", value));
+    }
+
+    if !value.contains('\'') {
+        return Ok(format!("'{}'", value));
     }
 
-    result_query
+    Err(invalid_parameter(
+        index,
+        "contains both single and double quotes, which a GitQL string cannot represent",
+    ))
+}
+
+fn postgres_epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
 }
 
-pub fn get_param(portal: &Portal<String>, i: usize) -> Option<String> {
-    let param_type = portal.statement.parameter_types.get(i).unwrap();
-    portal.parameter::<String>(i, param_type).unwrap()
+fn invalid_parameter(index: usize, reason: &str) -> PgWireError {
+    bind_error("22P02", format!("parameter ${} {}", index + 1, reason))
+}
+
+fn bind_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use pgwire::api::portal::Format;
+    use pgwire::api::stmt::StoredStatement;
+
+    use super::*;
+
+    fn portal(
+        statement: &str,
+        parameter_types: Vec<Type>,
+        parameter_format: Format,
+        parameters: &[Option<&[u8]>],
+    ) -> Portal<String> {
+        Portal {
+            name: String::new(),
+            statement: Arc::new(StoredStatement {
+                id: String::new(),
+                statement: statement.to_owned(),
+                parameter_types,
+            }),
+            parameter_format,
+            parameters: parameters
+                .iter()
+                .map(|parameter| parameter.map(Bytes::copy_from_slice))
+                .collect(),
+            result_column_format: Format::UnifiedText,
+        }
+    }
+
+    fn bind_text(statement: &str, parameters: &[&str]) -> PgWireResult<String> {
+        let parameters = parameters
+            .iter()
+            .map(|parameter| Some(parameter.as_bytes()))
+            .collect::<Vec<_>>();
+        let portal = portal(statement, vec![], Format::UnifiedText, &parameters);
+        bind_parameters(&portal).map(|bound| bound.query)
+    }
+
+    fn error_code(result: PgWireResult<String>) -> String {
+        match result {
+            Err(PgWireError::UserError(info)) => info.code.clone(),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(query) => panic!("unexpected query: {}", query),
+        }
+    }
+
+    #[test]
+    fn text_parameters_are_quoted() {
+        let query = bind_text("SELECT * FROM commits WHERE author_name = $1", &["Jane"]);
+        assert_eq!(
+            query.unwrap(),
+            "SELECT * FROM commits WHERE author_name = \"Jane\""
+        );
+    }
+
+    #[test]
+    fn text_parameters_with_double_quotes_use_single_quotes() {
+        let query = bind_text("SELECT * FROM commits WHERE title = $1", &["say \"hi\""]);
+        assert_eq!(
+            query.unwrap(),
+            "SELECT * FROM commits WHERE title = 'say \"hi\"'"
+        );
+    }
+
+    #[test]
+    fn text_parameters_cannot_close_their_literal() {
+        let query = bind_text(
+            "SELECT * FROM commits WHERE title = $1",
+            &["x\" OR 1 = 1 OR \"x"],
+        );
+        assert_eq!(
+            query.unwrap(),
+            "SELECT * FROM commits WHERE title = 'x\" OR 1 = 1 OR \"x'"
+        );
+
+        let query = bind_text("SELECT * FROM commits WHERE title = $1", &["'\" OR 1 = 1"]);
+        assert_eq!(error_code(query), "22P02");
+    }
+
+    #[test]
+    fn placeholders_inside_string_literals_are_not_bound() {
+        let query = bind_text(
+            "SELECT * FROM commits WHERE title = '$1' AND author_name = \"$1\" OR title = $1",
+            &["\" OR 1 = 1 OR title = \""],
+        );
+        assert_eq!(
+            query.unwrap(),
+            "SELECT * FROM commits WHERE title = '$1' AND author_name = \"$1\" OR title = '\" OR 1 = 1 OR title = \"'"
+        );
+    }
+
+    #[test]
+    fn text_parameters_are_typed_from_their_column() {
+        let query = bind_text("SELECT * FROM commits LIMIT $1", &["10"]);
+        assert_eq!(query.unwrap(), "SELECT * FROM commits LIMIT 10");
+
+        let query = bind_text("SELECT * FROM commits LIMIT $1", &["10; DROP"]);
+        assert_eq!(error_code(query), "22P02");
+    }
+
+    #[test]
+    fn missing_parameters_are_rejected() {
+        assert_eq!(error_code(bind_text("SELECT $2", &["a"])), "42P02");
+        assert_eq!(error_code(bind_text("SELECT $0", &["a"])), "42P02");
+    }
+
+    #[test]
+    fn null_parameters_are_bound_as_null() {
+        let portal = portal("SELECT $1", vec![], Format::UnifiedText, &[None]);
+        assert_eq!(bind_parameters(&portal).unwrap().query, "SELECT null");
+    }
+
+    #[test]
+    fn binary_parameters_are_decoded_by_type() {
+        let statement = "SELECT $1, $2, $3, $4";
+        let (int, float) = (42i32.to_be_bytes(), 1.5f64.to_be_bytes());
+        let (timestamp, date) = (86_400_000_000i64.to_be_bytes(), 1i32.to_be_bytes());
+        let parameters: [Option<&[u8]>; 4] =
+            [Some(&int), Some(&float), Some(&timestamp), Some(&date)];
+        let types = vec![Type::INT4, Type::FLOAT8, Type::TIMESTAMP, Type::DATE];
+        let portal = portal(statement, types, Format::UnifiedBinary, &parameters);
+        assert_eq!(
+            bind_parameters(&portal).unwrap().query,
+            "SELECT 42, 1.5, \"2000-01-02 00:00:00\", \"2000-01-02\""
+        );
+    }
+
+    #[test]
+    fn binary_parameters_of_the_wrong_size_are_rejected() {
+        let parameters: [Option<&[u8]>; 1] = [Some(&[0, 1])];
+        let portal = portal(
+            "SELECT $1",
+            vec![Type::INT8],
+            Format::UnifiedBinary,
+            &parameters,
+        );
+        let result = bind_parameters(&portal).map(|bound| bound.query);
+        assert_eq!(error_code(result), "22P02");
+    }
+
+    #[test]
+    fn positions_point_at_the_unbound_statement() {
+        let portal = portal(
+            "SELECT $1 FROM commits WHERE title = $2 AND x",
+            vec![Type::TEXT, Type::TEXT],
+            Format::UnifiedText,
+            &[Some(&b"abc"[..]), Some(&b"d"[..])],
+        );
+        let bound = bind_parameters(&portal).unwrap();
+        assert_eq!(
+            bound.query,
+            "SELECT \"abc\" FROM commits WHERE title = \"d\" AND x"
+        );
+
+        assert_eq!(bound.unbound_position(14), 11);
+        assert_eq!(bound.unbound_position(9), 8);
+        assert_eq!(bound.unbound_position(42), 38);
+        assert_eq!(bound.unbound_position(45), 41);
+    }
 }
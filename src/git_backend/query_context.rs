@@ -0,0 +1,187 @@
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use gitql_ast::object::Row;
+use gitql_ast::value::Value;
+
+use crate::config::QueryLimits;
+use crate::metrics;
+
+const QUERY_CANCELED: &str = "57014";
+const PROGRAM_LIMIT_EXCEEDED: &str = "54000";
+
+#[derive(Clone, Default)]
+pub struct QueryStats {
+    pub phases: Vec<(&'static str, Duration)>,
+    pub repositories: Vec<RepositoryStats>,
+}
+
+#[derive(Clone)]
+pub struct RepositoryStats {
+    pub name: String,
+    pub table: String,
+    pub duration: Duration,
+    pub rows: usize,
+    pub commits: u64,
+}
+
+#[derive(Clone)]
+pub struct Interruption {
+    pub code: &'static str,
+    pub message: String,
+}
+
+pub struct QueryContext {
+    started: Instant,
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    limits: QueryLimits,
+    commits: AtomicU64,
+    diff_bytes: AtomicU64,
+    memory_bytes: AtomicU64,
+    interruption: Mutex<Option<Interruption>>,
+    stats: Mutex<QueryStats>,
+}
+
+impl QueryContext {
+    pub fn new(timeout: Option<Duration>, limits: QueryLimits) -> QueryContext {
+        QueryContext {
+            started: Instant::now(),
+            cancelled: AtomicBool::new(false),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            limits,
+            commits: AtomicU64::new(0),
+            diff_bytes: AtomicU64::new(0),
+            memory_bytes: AtomicU64::new(0),
+            interruption: Mutex::new(None),
+            stats: Mutex::new(QueryStats::default()),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn record_phase(&self, phase: &'static str, duration: Duration) {
+        self.stats.lock().unwrap().phases.push((phase, duration));
+    }
+
+    pub fn record_repository(&self, stats: RepositoryStats) {
+        self.stats.lock().unwrap().repositories.push(stats);
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// The number of commits walked so far, across all repositories.
+    pub fn commits(&self) -> u64 {
+        self.commits.load(Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(interruption) = self.interruption() {
+            return Err(interruption.message);
+        }
+
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(self.interrupt(
+                QUERY_CANCELED,
+                "canceling statement due to user request".to_owned(),
+            ));
+        }
+
+        if self
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            return Err(self.interrupt(
+                QUERY_CANCELED,
+                "canceling statement due to statement timeout".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn count_commit(&self) -> Result<(), String> {
+        self.check()?;
+        let commits = self.commits.fetch_add(1, Ordering::SeqCst) + 1;
+        metrics::COMMITS_WALKED.inc();
+        self.check_limit(
+            "max_commits",
+            "commits walked",
+            commits,
+            self.limits.max_commits,
+        )
+    }
+
+    pub fn count_diff_bytes(&self, bytes: u64) -> Result<(), String> {
+        let diff_bytes = self.diff_bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.check_limit(
+            "max_diff_bytes",
+            "blob bytes diffed",
+            diff_bytes,
+            self.limits.max_diff_bytes,
+        )
+    }
+
+    pub fn count_row(&self, row: &Row) -> Result<(), String> {
+        let bytes = row_size(row);
+        let memory_bytes = self.memory_bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.check_limit(
+            "max_memory_bytes",
+            "bytes of row data",
+            memory_bytes,
+            self.limits.max_memory_bytes,
+        )
+    }
+
+    pub fn check_result_rows(&self, rows: usize) -> Result<(), String> {
+        self.check_limit("max_rows", "result rows", rows as u64, self.limits.max_rows)
+    }
+
+    pub fn interruption(&self) -> Option<Interruption> {
+        self.interruption.lock().unwrap().clone()
+    }
+
+    fn check_limit(&self, name: &str, what: &str, used: u64, limit: u64) -> Result<(), String> {
+        if limit == 0 || used <= limit {
+            return Ok(());
+        }
+
+        Err(self.interrupt(
+            PROGRAM_LIMIT_EXCEEDED,
+            format!(
+                "query exceeded the {} limit: more than {} {}",
+                name, limit, what
+            ),
+        ))
+    }
+
+    fn interrupt(&self, code: &'static str, message: String) -> String {
+        let mut interruption = self.interruption.lock().unwrap();
+        interruption
+            .get_or_insert(Interruption { code, message })
+            .message
+            .clone()
+    }
+}
+
+fn row_size(row: &Row) -> u64 {
+    let size = row
+        .values
+        .iter()
+        .map(|value| match value {
+            Value::Text(text) => mem::size_of::<Value>() + text.len(),
+            _ => mem::size_of::<Value>(),
+        })
+        .sum::<usize>();
+    (mem::size_of::<Row>() + size) as u64
+}
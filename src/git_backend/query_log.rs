@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use pgwire::error::PgWireError;
+
+use super::query_context::RepositoryStats;
+use super::QueryContext;
+use crate::audit::{self, AuditLog, AuditRecord};
+use crate::metrics;
+
+/// Everything needed to log a finished query once the client is no longer
+/// at hand, e.g. at the end of a streamed result.
+pub struct QueryRecorder {
+    pub session_id: Option<i32>,
+    pub user: String,
+    pub client_addr: String,
+    pub database: Option<String>,
+    pub application_name: Option<String>,
+    pub audit_log: Option<Arc<AuditLog>>,
+}
+
+impl QueryRecorder {
+    pub fn record(&self, query: &str, context: &QueryContext, result: Result<usize, &PgWireError>) {
+        log_query(self.session_id, &self.user, query, context, result);
+
+        if let Some(audit_log) = &self.audit_log {
+            let mut repositories = context
+                .stats()
+                .repositories
+                .into_iter()
+                .map(|repository| repository.name)
+                .collect::<Vec<_>>();
+            repositories.sort();
+            repositories.dedup();
+
+            audit_log.record(&AuditRecord {
+                timestamp: audit::timestamp(),
+                user: &self.user,
+                client_addr: self.client_addr.clone(),
+                database: self.database.as_deref(),
+                application_name: self.application_name.as_deref(),
+                query: audit::normalize_query(query),
+                repositories,
+                rows: result.ok(),
+                error: result.err().map(|err| err.to_string()),
+            });
+        }
+    }
+}
+
+fn log_query(
+    session_id: Option<i32>,
+    user: &str,
+    query: &str,
+    context: &QueryContext,
+    result: Result<usize, &PgWireError>,
+) {
+    let stats = context.stats();
+    record_metrics(context, &stats.repositories, &result);
+
+    let phase = |name: &str| {
+        stats
+            .phases
+            .iter()
+            .filter(|(phase, _)| *phase == name)
+            .map(|(_, duration)| *duration)
+            .sum::<Duration>()
+    };
+    let repositories = stats
+        .repositories
+        .iter()
+        .map(|repository| {
+            format!(
+                "{}/{}={:.3}ms/{}rows",
+                repository.name,
+                repository.table,
+                millis(repository.duration),
+                repository.rows
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let session_id = session_id.unwrap_or_default();
+    let duration_ms = millis(context.elapsed());
+    let tokenize_ms = millis(phase("tokenize"));
+    let parse_ms = millis(phase("parse"));
+    let evaluate_ms = millis(phase("evaluate"));
+    let encode_ms = millis(phase("encode"));
+
+    match result {
+        Ok(rows) => tracing::info!(
+            session_id,
+            user,
+            query,
+            tokenize_ms,
+            parse_ms,
+            evaluate_ms,
+            evaluate_repositories = repositories.as_str(),
+            encode_ms,
+            duration_ms,
+            rows,
+            "query finished"
+        ),
+        Err(err) => tracing::warn!(
+            session_id,
+            user,
+            query,
+            tokenize_ms,
+            parse_ms,
+            evaluate_ms,
+            evaluate_repositories = repositories.as_str(),
+            encode_ms,
+            duration_ms,
+            error = %err,
+            "query failed"
+        ),
+    }
+}
+
+fn record_metrics(
+    context: &QueryContext,
+    repositories: &[RepositoryStats],
+    result: &Result<usize, &PgWireError>,
+) {
+    let outcome = match result {
+        Ok(_) => "success",
+        Err(_) => match context.interruption() {
+            Some(interruption) if interruption.code == "57014" => "canceled",
+            Some(_) => "limit_exceeded",
+            None => "error",
+        },
+    };
+    metrics::QUERIES.with_label_values(&[outcome]).inc();
+
+    let mut tables = repositories
+        .iter()
+        .map(|repository| repository.table.as_str())
+        .collect::<Vec<_>>();
+    tables.sort_unstable();
+    tables.dedup();
+    if tables.is_empty() {
+        tables.push("none");
+    }
+
+    let seconds = context.elapsed().as_secs_f64();
+    for table in tables {
+        metrics::QUERY_DURATION
+            .with_label_values(&[table])
+            .observe(seconds);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use gitql_ast::value::Value;
+use pgwire::api::portal::Format;
+use pgwire::api::results::FieldInfo;
+use pgwire::api::Type;
+use pgwire::error::PgWireResult;
+use pgwire::messages::data::DataRow;
+
+use super::git_row::encode_values;
+
+/// Rows computed by the server itself, such as settings and catalog
+/// relations, returned without going through the GitQL engine.
+#[derive(Default)]
+pub struct ResultSet {
+    pub columns: Vec<(String, Type)>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    pub fn new(columns: &[(&str, Type)]) -> ResultSet {
+        ResultSet {
+            columns: columns
+                .iter()
+                .map(|(name, column_type)| (name.to_string(), column_type.clone()))
+                .collect(),
+            rows: vec![],
+        }
+    }
+
+    pub fn fields(&self, format: &Format) -> Arc<Vec<FieldInfo>> {
+        let fields = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, (name, column_type))| {
+                FieldInfo::new(
+                    name.clone(),
+                    None,
+                    None,
+                    column_type.clone(),
+                    format.format_for(index),
+                )
+            })
+            .collect();
+        Arc::new(fields)
+    }
+
+    pub fn data_rows(
+        self,
+        fields_info: Arc<Vec<FieldInfo>>,
+    ) -> BoxStream<'static, PgWireResult<DataRow>> {
+        let data_rows = self
+            .rows
+            .iter()
+            .map(|row| encode_values(row, fields_info.clone()))
+            .collect::<Vec<_>>();
+        stream::iter(data_rows).boxed()
+    }
+}
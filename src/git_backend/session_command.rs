@@ -0,0 +1,279 @@
+/// Session and transaction commands answered by the server itself instead of
+/// being sent to the GitQL parser.
+///
+/// Queries are read only, so transactions are accepted but have no effect:
+/// BEGIN and COMMIT only move the reported transaction status, and an error
+/// inside a transaction block does not abort it, so later statements still
+/// run instead of failing until ROLLBACK.
+pub enum SessionCommand {
+    Begin,
+    Commit,
+    Rollback,
+    /// `SET name TO value`, where `None` stands for `DEFAULT`.
+    Set(String, Option<String>),
+    /// `SET TRANSACTION ...` and `SET SESSION CHARACTERISTICS ...`, accepted
+    /// and ignored because queries are read only.
+    SetTransaction,
+    /// `RESET name`, where `None` stands for `RESET ALL`.
+    Reset(Option<String>),
+    DiscardAll,
+    /// `DEALLOCATE name`, where `None` stands for `DEALLOCATE ALL`.
+    Deallocate(Option<String>),
+    /// `CLOSE name`, where `None` stands for `CLOSE ALL`.
+    Close(Option<String>),
+}
+
+impl SessionCommand {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            SessionCommand::Begin => "BEGIN",
+            SessionCommand::Commit => "COMMIT",
+            SessionCommand::Rollback => "ROLLBACK",
+            SessionCommand::Set(..) | SessionCommand::SetTransaction => "SET",
+            SessionCommand::Reset(_) => "RESET",
+            SessionCommand::DiscardAll => "DISCARD ALL",
+            SessionCommand::Deallocate(Some(_)) => "DEALLOCATE",
+            SessionCommand::Deallocate(None) => "DEALLOCATE ALL",
+            SessionCommand::Close(Some(_)) => "CLOSE CURSOR",
+            SessionCommand::Close(None) => "CLOSE CURSOR ALL",
+        }
+    }
+}
+
+/// Recognizes a session command, returning `None` for anything that should
+/// be evaluated as a GitQL query and an error for a malformed command.
+pub fn parse_session_command(query: &str) -> Option<Result<SessionCommand, String>> {
+    let query = query.trim().trim_end_matches(';').trim();
+    let lowercase = query.to_lowercase();
+    let words = lowercase.split_whitespace().collect::<Vec<_>>();
+
+    let command = match words.as_slice() {
+        ["begin", ..] | ["start", "transaction", ..] => SessionCommand::Begin,
+        ["commit", ..] | ["end", ..] => SessionCommand::Commit,
+        ["rollback", ..] | ["abort", ..] => SessionCommand::Rollback,
+        ["discard", "all"] => SessionCommand::DiscardAll,
+        ["discard", ..] => return Some(Err(syntax_error(query))),
+        ["deallocate", "all"] | ["deallocate", "prepare", "all"] => {
+            SessionCommand::Deallocate(None)
+        }
+        ["deallocate", "prepare", name] | ["deallocate", name] => {
+            SessionCommand::Deallocate(Some(identifier(query, name)))
+        }
+        ["close", "all"] => SessionCommand::Close(None),
+        ["close", name] => SessionCommand::Close(Some(identifier(query, name))),
+        ["reset", "all"] => SessionCommand::Reset(None),
+        ["reset", name] => SessionCommand::Reset(Some(parameter_name(name))),
+        ["set", rest @ ..] if !rest.first().map_or(true, |word| word.starts_with('@')) => {
+            return Some(parse_set(query));
+        }
+        _ => return None,
+    };
+
+    Some(Ok(command))
+}
+
+fn parse_set(query: &str) -> Result<SessionCommand, String> {
+    let mut rest = query[3..].trim_start();
+    for scope in ["session ", "local "] {
+        if starts_with_keyword(rest, scope) && !starts_with_keyword(rest, "session characteristics")
+        {
+            rest = rest[scope.len()..].trim_start();
+        }
+    }
+
+    if starts_with_keyword(rest, "transaction")
+        || starts_with_keyword(rest, "session characteristics")
+    {
+        return Ok(SessionCommand::SetTransaction);
+    }
+
+    if starts_with_keyword(rest, "time zone ") {
+        let value = rest["time zone ".len()..].trim();
+        let value = parameter_value(value).filter(|value| !value.eq_ignore_ascii_case("local"));
+        return Ok(SessionCommand::Set("timezone".to_owned(), value));
+    }
+
+    if starts_with_keyword(rest, "names ") {
+        let value = rest["names ".len()..].trim();
+        return Ok(SessionCommand::Set(
+            "client_encoding".to_owned(),
+            parameter_value(value),
+        ));
+    }
+
+    let name_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(rest.len());
+    let (name, value) = rest.split_at(name_len);
+    let value = value.trim_start();
+    let value = if let Some(value) = value.strip_prefix('=') {
+        value
+    } else if starts_with_keyword(value, "to ") {
+        &value["to ".len()..]
+    } else {
+        return Err(syntax_error(query));
+    };
+
+    if name.is_empty() || value.trim().is_empty() {
+        return Err(syntax_error(query));
+    }
+
+    Ok(SessionCommand::Set(
+        parameter_name(name),
+        parameter_value(value.trim()),
+    ))
+}
+
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.get(..keyword.len())
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case(keyword))
+}
+
+fn parameter_name(name: &str) -> String {
+    name.trim_matches('"').to_lowercase()
+}
+
+fn parameter_value(value: &str) -> Option<String> {
+    if value.eq_ignore_ascii_case("default") {
+        return None;
+    }
+
+    let value = value
+        .split(',')
+        .map(|part| part.trim().trim_matches('\'').trim_matches('"'))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(value)
+}
+
+/// Keeps the case of a quoted identifier and folds an unquoted one, taking
+/// the original spelling from the query.
+fn identifier(query: &str, lowercase: &str) -> String {
+    let original = query
+        .split_whitespace()
+        .find(|word| word.to_lowercase() == lowercase)
+        .unwrap_or(lowercase);
+
+    match original
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        Some(quoted) => quoted.to_owned(),
+        None => lowercase.to_owned(),
+    }
+}
+
+fn syntax_error(query: &str) -> String {
+    format!("syntax error in session command \"{}\"", query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> SessionCommand {
+        match parse_session_command(query) {
+            Some(Ok(command)) => command,
+            Some(Err(err)) => panic!("{}", err),
+            None => panic!("{} is not a session command", query),
+        }
+    }
+
+    fn set(query: &str) -> (String, Option<String>) {
+        match parse(query) {
+            SessionCommand::Set(name, value) => (name, value),
+            command => panic!("{} parsed as {}", query, command.tag()),
+        }
+    }
+
+    #[test]
+    fn transaction_commands_are_recognized() {
+        assert!(matches!(parse("BEGIN"), SessionCommand::Begin));
+        assert!(matches!(
+            parse("start transaction read only;"),
+            SessionCommand::Begin
+        ));
+        assert!(matches!(parse("END"), SessionCommand::Commit));
+        assert!(matches!(parse("abort"), SessionCommand::Rollback));
+    }
+
+    #[test]
+    fn set_accepts_to_and_equals() {
+        assert_eq!(
+            set("SET search_path TO public, 'git'"),
+            ("search_path".to_owned(), Some("public, git".to_owned()))
+        );
+        assert_eq!(
+            set("set application_name = 'psql';"),
+            ("application_name".to_owned(), Some("psql".to_owned()))
+        );
+        assert_eq!(
+            set("SET SESSION statement_timeout TO DEFAULT"),
+            ("statement_timeout".to_owned(), None)
+        );
+    }
+
+    #[test]
+    fn set_time_zone_and_names_use_their_parameters() {
+        assert_eq!(
+            set("SET TIME ZONE 'UTC'"),
+            ("timezone".to_owned(), Some("UTC".to_owned()))
+        );
+        assert_eq!(set("SET TIME ZONE LOCAL"), ("timezone".to_owned(), None));
+        assert_eq!(
+            set("SET NAMES 'UTF8'"),
+            ("client_encoding".to_owned(), Some("UTF8".to_owned()))
+        );
+    }
+
+    #[test]
+    fn set_transaction_is_accepted() {
+        assert!(matches!(
+            parse("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE"),
+            SessionCommand::SetTransaction
+        ));
+        assert!(matches!(
+            parse("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY"),
+            SessionCommand::SetTransaction
+        ));
+    }
+
+    #[test]
+    fn malformed_commands_are_errors() {
+        assert!(matches!(parse_session_command("SET x"), Some(Err(_))));
+        assert!(matches!(
+            parse_session_command("DISCARD PLANS"),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn queries_are_not_session_commands() {
+        assert!(parse_session_command("SELECT * FROM commits").is_none());
+        assert!(parse_session_command("SET @name = 'value'").is_none());
+    }
+
+    #[test]
+    fn names_keep_the_case_of_quoted_identifiers() {
+        assert!(matches!(
+            parse("DEALLOCATE PREPARE \"Stmt1\""),
+            SessionCommand::Deallocate(Some(name)) if name == "Stmt1"
+        ));
+        assert!(matches!(
+            parse("CLOSE Cursor1"),
+            SessionCommand::Close(Some(name)) if name == "cursor1"
+        ));
+        assert!(matches!(
+            parse("RESET \"TimeZone\""),
+            SessionCommand::Reset(Some(name)) if name == "timezone"
+        ));
+    }
+
+    #[test]
+    fn all_forms_are_recognized() {
+        assert!(matches!(parse("RESET ALL"), SessionCommand::Reset(None)));
+        assert!(matches!(parse("DISCARD ALL"), SessionCommand::DiscardAll));
+        assert!(matches!(parse("CLOSE ALL"), SessionCommand::Close(None)));
+        assert_eq!(parse("DEALLOCATE ALL").tag(), "DEALLOCATE ALL");
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use gitql_ast::value::Value;
+use pgwire::api::auth::DefaultServerParameterProvider;
+use pgwire::api::Type;
+
+use super::result_set::ResultSet;
+
+/// Settings reported by SHOW, with their canonical spelling and description.
+const SETTINGS: &[(&str, &str)] = &[
+    (
+        "application_name",
+        "Sets the application name to be reported in statistics and logs.",
+    ),
+    (
+        "client_encoding",
+        "Sets the client's character set encoding.",
+    ),
+    (
+        "DateStyle",
+        "Sets the display format for date and time values.",
+    ),
+    (
+        "default_transaction_isolation",
+        "Sets the transaction isolation level of each new transaction.",
+    ),
+    (
+        "default_transaction_read_only",
+        "Sets the default read-only status of new transactions.",
+    ),
+    (
+        "integer_datetimes",
+        "Shows whether datetimes are integer based.",
+    ),
+    (
+        "is_superuser",
+        "Shows whether the current user is a superuser.",
+    ),
+    (
+        "max_identifier_length",
+        "Shows the maximum identifier length.",
+    ),
+    (
+        "search_path",
+        "Sets the schema search order for names that are not schema-qualified.",
+    ),
+    (
+        "server_encoding",
+        "Shows the server (database) character set encoding.",
+    ),
+    ("server_version", "Shows the server version."),
+    (
+        "server_version_num",
+        "Shows the server version as an integer.",
+    ),
+    ("session_authorization", "Sets the session user name."),
+    (
+        "standard_conforming_strings",
+        "Causes '...' strings to treat backslashes literally.",
+    ),
+    (
+        "statement_timeout",
+        "Sets the maximum allowed duration of any statement.",
+    ),
+    (
+        "TimeZone",
+        "Sets the time zone for displaying and interpreting time stamps.",
+    ),
+    (
+        "transaction_isolation",
+        "Sets the current transaction's isolation level.",
+    ),
+    (
+        "transaction_read_only",
+        "Sets the current transaction's read-only status.",
+    ),
+];
+
+/// Settings reported to the client with ParameterStatus whenever their value
+/// changes, the GUC_REPORT parameters of PostgreSQL.
+const REPORTED_SETTINGS: &[&str] = &[
+    "application_name",
+    "client_encoding",
+    "DateStyle",
+    "default_transaction_read_only",
+    "integer_datetimes",
+    "is_superuser",
+    "server_encoding",
+    "server_version",
+    "session_authorization",
+    "standard_conforming_strings",
+    "TimeZone",
+];
+
+/// Queries about the server configuration that are answered without the
+/// GitQL engine.
+pub enum SettingsQuery {
+    Show(String),
+    ShowAll,
+    Version,
+    CurrentSchema,
+}
+
+pub fn parse_settings_query(query: &str) -> Option<SettingsQuery> {
+    let query = query.trim().trim_end_matches(';').trim();
+    let normalized = query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    match normalized.as_str() {
+        "select version()" => Some(SettingsQuery::Version),
+        "select current_schema()" | "select current_schema" => Some(SettingsQuery::CurrentSchema),
+        "show all" => Some(SettingsQuery::ShowAll),
+        "show time zone" => Some(SettingsQuery::Show("timezone".to_owned())),
+        "show transaction isolation level" => {
+            Some(SettingsQuery::Show("transaction_isolation".to_owned()))
+        }
+        "show session authorization" => {
+            Some(SettingsQuery::Show("session_authorization".to_owned()))
+        }
+        _ => normalized
+            .strip_prefix("show ")
+            .filter(|name| !name.contains(' '))
+            .map(|name| SettingsQuery::Show(name.trim_matches('"').to_owned())),
+    }
+}
+
+/// Snapshot of the server parameters and session state of one connection.
+pub struct Settings {
+    pub server: Arc<DefaultServerParameterProvider>,
+    pub session: HashMap<String, String>,
+    pub statement_timeout: Option<Duration>,
+    pub user: String,
+    pub application_name: Option<String>,
+}
+
+impl Settings {
+    pub fn evaluate(&self, query: &SettingsQuery) -> Result<ResultSet, String> {
+        let (column, value) = match query {
+            SettingsQuery::Show(name) => {
+                let value = self
+                    .get(name)
+                    .ok_or_else(|| format!("unrecognized configuration parameter \"{}\"", name))?;
+                (canonical_name(name), value)
+            }
+            SettingsQuery::ShowAll => {
+                let mut result = ResultSet::new(&[
+                    ("name", Type::TEXT),
+                    ("setting", Type::TEXT),
+                    ("description", Type::TEXT),
+                ]);
+                result.rows = self
+                    .all()
+                    .into_iter()
+                    .map(|(name, value, description)| {
+                        vec![
+                            Value::Text(name),
+                            Value::Text(value),
+                            Value::Text(description.to_owned()),
+                        ]
+                    })
+                    .collect();
+                return Ok(result);
+            }
+            SettingsQuery::Version => (
+                "version".to_owned(),
+                format!(
+                    "PostgreSQL {} (gql-server {})",
+                    self.server.server_version,
+                    env!("CARGO_PKG_VERSION")
+                ),
+            ),
+            SettingsQuery::CurrentSchema => ("current_schema".to_owned(), "public".to_owned()),
+        };
+
+        let mut result = ResultSet::new(&[(column.as_str(), Type::TEXT)]);
+        result.rows.push(vec![Value::Text(value)]);
+        Ok(result)
+    }
+
+    /// Returns every known setting and every parameter set in the session as
+    /// name, value and description.
+    pub fn all(&self) -> Vec<(String, String, &'static str)> {
+        let mut settings = SETTINGS
+            .iter()
+            .map(|(name, description)| {
+                let value = self.get(name).unwrap_or_default();
+                (name.to_string(), value, *description)
+            })
+            .collect::<Vec<_>>();
+
+        let mut session = self
+            .session
+            .iter()
+            .filter(|(name, _)| {
+                !SETTINGS
+                    .iter()
+                    .any(|(known, _)| known.eq_ignore_ascii_case(name))
+            })
+            .map(|(name, value)| (name.clone(), value.clone(), ""))
+            .collect::<Vec<_>>();
+        session.sort();
+        settings.append(&mut session);
+        settings
+    }
+
+    /// Returns the name and current value of every setting reported to the
+    /// client with ParameterStatus.
+    pub fn reported(&self) -> Vec<(String, String)> {
+        REPORTED_SETTINGS
+            .iter()
+            .map(|name| (name.to_string(), self.get(name).unwrap_or_default()))
+            .collect()
+    }
+
+    /// Returns the current value of a setting, preferring a value set in the
+    /// session over the server default.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        if let Some(value) = self.session.get(&name) {
+            return Some(value.clone());
+        }
+
+        let value = match name.as_str() {
+            "application_name" => self.application_name.clone().unwrap_or_default(),
+            "client_encoding" => self.server.client_encoding.clone(),
+            "datestyle" => self.server.date_style.clone(),
+            "integer_datetimes" => self.server.integer_datetimes.clone(),
+            "is_superuser" => "off".to_owned(),
+            "max_identifier_length" => "63".to_owned(),
+            "search_path" => "\"$user\", public".to_owned(),
+            "server_encoding" => self.server.server_encoding.clone(),
+            "server_version" => self.server.server_version.clone(),
+            "server_version_num" => server_version_num(&self.server.server_version).to_string(),
+            "session_authorization" => self.user.clone(),
+            "standard_conforming_strings" => "on".to_owned(),
+            "statement_timeout" => format_timeout(self.statement_timeout),
+            "timezone" => "UTC".to_owned(),
+            "transaction_isolation" | "default_transaction_isolation" => {
+                "read committed".to_owned()
+            }
+            "transaction_read_only" | "default_transaction_read_only" => "on".to_owned(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+fn canonical_name(name: &str) -> String {
+    SETTINGS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map_or_else(|| name.to_owned(), |(known, _)| known.to_string())
+}
+
+/// Converts a version such as `15.4` or `9.6.1` to the `server_version_num`
+/// form clients compare against, e.g. `150004` or `90601`.
+fn server_version_num(version: &str) -> u32 {
+    let parts = version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let part = |index: usize| parts.get(index).copied().unwrap_or(0);
+
+    if part(0) >= 10 {
+        part(0) * 10000 + part(1)
+    } else {
+        part(0) * 10000 + part(1) * 100 + part(2)
+    }
+}
+
+fn format_timeout(timeout: Option<Duration>) -> String {
+    match timeout.map(|timeout| timeout.as_millis()) {
+        None | Some(0) => "0".to_owned(),
+        Some(millis) if millis % 1000 == 0 => format!("{}s", millis / 1000),
+        Some(millis) => format!("{}ms", millis),
+    }
+}
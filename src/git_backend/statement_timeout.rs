@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// Parses a `statement_timeout` value such as `5000`, `5s` or `1min`, where
+/// zero disables the timeout.
+pub fn parse_statement_timeout(value: &str) -> Result<Option<Duration>, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount = amount.parse::<u64>().map_err(|_| {
+        format!(
+            "invalid value for parameter \"statement_timeout\": \"{}\"",
+            value
+        )
+    })?;
+    let multiplier = match unit.trim() {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "min" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => {
+            return Err(format!(
+                "invalid value for parameter \"statement_timeout\": \"{}\"",
+                value
+            ))
+        }
+    };
+    let milliseconds = amount.checked_mul(multiplier).ok_or_else(|| {
+        format!(
+            "{} is outside the valid range for parameter \"statement_timeout\"",
+            value
+        )
+    })?;
+
+    if milliseconds == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Duration::from_millis(milliseconds)))
+}
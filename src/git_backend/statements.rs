@@ -0,0 +1,250 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Splits a simple query string on the semicolons that are not inside string
+/// literals, dollar-quoted strings or comments, returning every statement with
+/// its byte offset in `query`. Comments before a statement are dropped, and so
+/// are statements that are empty or only hold comments.
+pub fn split_statements(query: &str) -> Vec<(usize, &str)> {
+    let mut statements = vec![];
+    let mut chars = query.char_indices().peekable();
+    let mut start = 0;
+    let mut has_content = false;
+    let mut previous = None;
+
+    while let Some((index, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        match c {
+            '\'' | '"' => {
+                let end = query[index + 1..]
+                    .find(c)
+                    .map_or(query.len(), |end| index + 1 + end + 1);
+                skip_to(&mut chars, end);
+            }
+            '-' if next == Some('-') => {
+                let end = query[index..]
+                    .find('\n')
+                    .map_or(query.len(), |end| index + end);
+                skip_to(&mut chars, end);
+                continue;
+            }
+            '/' if next == Some('*') => {
+                skip_to(&mut chars, block_comment_end(query, index));
+                continue;
+            }
+            '$' if !previous.map_or(false, is_identifier_char) => {
+                if let Some(tag) = dollar_quote_tag(&query[index..]) {
+                    let body = index + tag.len();
+                    let end = query[body..]
+                        .find(tag)
+                        .map_or(query.len(), |end| body + end + tag.len());
+                    skip_to(&mut chars, end);
+                }
+            }
+            ';' => {
+                if has_content {
+                    statements.push((start, query[start..index].trim_end()));
+                }
+                has_content = false;
+                previous = Some(c);
+                continue;
+            }
+            _ => {}
+        }
+
+        if !has_content && !c.is_whitespace() {
+            start = index;
+            has_content = true;
+        }
+        previous = Some(c);
+    }
+
+    if has_content {
+        statements.push((start, query[start..].trim_end()));
+    }
+    statements
+}
+
+/// Advances `chars` to the byte offset `end`.
+fn skip_to(chars: &mut Peekable<CharIndices>, end: usize) {
+    while chars.peek().map_or(false, |(index, _)| *index < end) {
+        chars.next();
+    }
+}
+
+/// Returns the byte offset just past the block comment starting at `start`.
+/// Block comments nest, as in PostgreSQL.
+fn block_comment_end(query: &str, start: usize) -> usize {
+    let bytes = query.as_bytes();
+    let mut depth = 0;
+    let mut index = start;
+    while index + 1 < bytes.len() {
+        match (bytes[index], bytes[index + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                index += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                index += 2;
+                if depth == 0 {
+                    return index;
+                }
+            }
+            _ => index += 1,
+        }
+    }
+    query.len()
+}
+
+/// Returns the `$tag$` opening a dollar-quoted string at the start of `rest`.
+fn dollar_quote_tag(rest: &str) -> Option<&str> {
+    let end = rest[1..].find('$')? + 2;
+    let tag = &rest[1..end - 1];
+    let valid = tag
+        .chars()
+        .next()
+        .map_or(true, |first| first.is_alphabetic() || first == '_')
+        && tag.chars().all(is_identifier_char);
+    valid.then_some(&rest[..end])
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Returns the first statement of a query with its byte offset, for protocols
+/// that only accept one.
+pub fn first_statement(query: &str) -> (usize, &str) {
+    split_statements(query).first().copied().unwrap_or_default()
+}
+
+/// Splits a statement into identifier, literal, operator and punctuation
+/// tokens, keeping quoted literals whole along with their doubled quotes.
+pub fn split_tokens(statement: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = statement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut token = String::from(c);
+        if c == '\'' || c == '"' {
+            while let Some(next) = chars.next() {
+                token.push(next);
+                if next == c {
+                    match chars.next_if_eq(&c) {
+                        Some(quote) => token.push(quote),
+                        None => break,
+                    }
+                }
+            }
+        } else if c.is_alphanumeric() || c == '_' || c == '$' {
+            while let Some(next) = chars.peek() {
+                if !(next.is_alphanumeric() || *next == '_' || *next == '.') {
+                    break;
+                }
+                token.push(*next);
+                chars.next();
+            }
+        } else if matches!(c, '<' | '>' | '!' | '=') {
+            while let Some(next) = chars.peek() {
+                if !matches!(next, '<' | '>' | '=') {
+                    break;
+                }
+                token.push(*next);
+                chars.next();
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(query: &str) -> Vec<&str> {
+        split_statements(query)
+            .into_iter()
+            .map(|(offset, statement)| {
+                assert_eq!(&query[offset..offset + statement.len()], statement);
+                statement
+            })
+            .collect()
+    }
+
+    #[test]
+    fn statements_are_split_with_their_offsets() {
+        let query = "  SELECT 1 ;\n\n SELECT 2;  ;";
+        assert_eq!(
+            split_statements(query),
+            vec![(2, "SELECT 1"), (15, "SELECT 2")]
+        );
+    }
+
+    #[test]
+    fn semicolons_in_string_literals_do_not_split() {
+        let query = "SELECT 'a;b', \"c;d\"; SELECT 2";
+        assert_eq!(split(query), vec!["SELECT 'a;b', \"c;d\"", "SELECT 2"]);
+        assert_eq!(
+            split_statements(query)[1].0,
+            query.find("SELECT 2").unwrap()
+        );
+    }
+
+    #[test]
+    fn comments_do_not_split() {
+        let query =
+            "-- leading; comment\nSELECT 1 /* a; /* nested; */ b; */ ; SELECT 2 -- trailing;";
+        assert_eq!(
+            split(query),
+            vec![
+                "SELECT 1 /* a; /* nested; */ b; */",
+                "SELECT 2 -- trailing;"
+            ]
+        );
+        assert_eq!(
+            split_statements(query)[0].0,
+            query.find("SELECT 1").unwrap()
+        );
+    }
+
+    #[test]
+    fn statements_with_only_comments_are_dropped() {
+        assert!(split("-- nothing\n/* here */ ; ;").is_empty());
+        assert_eq!(first_statement("  "), (0, ""));
+    }
+
+    #[test]
+    fn dollar_quoted_strings_do_not_split() {
+        let query = "SELECT $$a;b$$; SELECT $tag$ c; $$ d $tag$; SELECT $1; SELECT a$b; SELECT 2";
+        assert_eq!(
+            split(query),
+            vec![
+                "SELECT $$a;b$$",
+                "SELECT $tag$ c; $$ d $tag$",
+                "SELECT $1",
+                "SELECT a$b",
+                "SELECT 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_keep_literals_and_operators_whole() {
+        assert_eq!(
+            split_tokens("title = 'a b' AND x<=$1"),
+            vec!["title", "=", "'a b'", "AND", "x", "<=", "$1"]
+        );
+        assert_eq!(
+            split_tokens("NULL 'it''s' ''''"),
+            vec!["NULL", "'it''s'", "''''"]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use gitql_ast::environment::Environment;
+use gitql_ast::object::Row;
+use gitql_ast::statement::{
+    GQLQuery, LimitStatement, OffsetStatement, Query, SelectStatement, WhereStatement,
+};
+use gitql_ast::value::Value;
+use gitql_engine::engine_evaluator::evaluate_expression;
+use pgwire::error::{PgWireError, PgWireResult};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use super::git_data_provider::GitDataProvider;
+use super::git_schema::TABLES_FIELDS_TYPES;
+use super::query_log::QueryRecorder;
+use super::{
+    interruption_error, parse_query, query_environment, user_error, validate_git_repositories,
+    QueryContext,
+};
+
+/// Number of encoded rows buffered ahead of the client before the revwalk
+/// waits for the socket to drain.
+const STREAM_BUFFER_ROWS: usize = 64;
+
+/// How often a walk waiting for the client checks whether the query was
+/// cancelled or timed out.
+const SEND_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the visible columns of `query` when its rows can be sent as soon as
+/// they are read: a projection of one table with optional WHERE, OFFSET and
+/// LIMIT clauses, without ordering, grouping, aggregation or DISTINCT.
+pub fn streaming_titles(query: &str) -> Option<Vec<String>> {
+    let mut env = query_environment();
+    let context = QueryContext::new(None, Default::default());
+    let query = parse_query(&mut env, &context, query).ok()?;
+    let query = streamable_query(&query)?;
+
+    let titles = statement::<SelectStatement>(query, "select")?
+        .fields_names
+        .iter()
+        .filter(|title| !query.hidden_selections.contains(title))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if titles
+        .iter()
+        .all(|title| TABLES_FIELDS_TYPES.contains_key(title.as_str()))
+    {
+        return Some(titles);
+    }
+
+    None
+}
+
+/// Evaluates `query` on a blocking thread and returns its rows, encoded by
+/// `encode`, as they are produced. The bounded channel makes the walk wait
+/// while the client is slow or the portal is suspended, still honouring
+/// cancellation and the statement timeout. Dropping the stream stops the walk
+/// at the next row it reads, or at once when it is waiting for the client.
+/// An error is reported after the rows already sent, so the walk never waits
+/// to deliver it.
+pub fn stream_rows<T, F>(
+    query: String,
+    repositories: Vec<String>,
+    context: Arc<QueryContext>,
+    encode: F,
+    recorder: QueryRecorder,
+) -> BoxStream<'static, PgWireResult<T>>
+where
+    T: Send + 'static,
+    F: Fn(&[&Value]) -> PgWireResult<T> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER_ROWS);
+    let failure = Arc::new(Mutex::new(None::<PgWireError>));
+    let runtime = Handle::current();
+
+    let produced = failure.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = produce_rows(&query, &repositories, &context, &encode, &sender, &runtime);
+        recorder.record(&query, &context, result.as_ref().copied());
+        if let Err(err) = result {
+            *produced.lock().unwrap() = Some(err);
+        }
+    });
+
+    stream::unfold(
+        (receiver, Some(failure)),
+        |(mut receiver, failure)| async move {
+            match receiver.recv().await {
+                Some(row) => Some((row, (receiver, failure))),
+                None => {
+                    let err = failure?.lock().unwrap().take()?;
+                    Some((Err(err), (receiver, None)))
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+fn produce_rows<T, F>(
+    query: &str,
+    repositories: &[String],
+    context: &Arc<QueryContext>,
+    encode: &F,
+    sender: &mpsc::Sender<PgWireResult<T>>,
+    runtime: &Handle,
+) -> PgWireResult<usize>
+where
+    F: Fn(&[&Value]) -> PgWireResult<T>,
+{
+    let repos = validate_git_repositories(repositories).map_err(|err| user_error("XX000", err))?;
+
+    let mut env = query_environment();
+    let query_node = parse_query(&mut env, context, query)?;
+    let query = streamable_query(&query_node)
+        .ok_or_else(|| user_error("XX000", "query cannot be streamed".to_owned()))?;
+
+    let select = statement::<SelectStatement>(query, "select").unwrap();
+    let condition =
+        statement::<WhereStatement>(query, "where").map(|statement| &statement.condition);
+    let mut offset = statement::<OffsetStatement>(query, "offset").map_or(0, |offset| offset.count);
+    let limit = statement::<LimitStatement>(query, "limit").map(|limit| limit.count);
+    let hidden_indexes = select
+        .fields_names
+        .iter()
+        .enumerate()
+        .filter(|(_, title)| query.hidden_selections.contains(title))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    let mut rows = 0;
+    if limit == Some(0) {
+        return Ok(rows);
+    }
+
+    let provider = GitDataProvider::new(repos, context.clone());
+    let started = Instant::now();
+    let result = provider.for_each_row(
+        &mut env,
+        &select.table_name,
+        &select.fields_names,
+        &select.fields_names,
+        &select.fields_values,
+        &mut |env: &mut Environment, row: Row| {
+            if sender.is_closed() {
+                return Ok(false);
+            }
+
+            if let Some(condition) = condition {
+                let matched =
+                    evaluate_expression(env, condition, &select.fields_names, &row.values)?;
+                if !matches!(matched, Value::Boolean(true)) {
+                    return Ok(true);
+                }
+            }
+
+            if offset > 0 {
+                offset -= 1;
+                return Ok(true);
+            }
+
+            rows += 1;
+            context.check_result_rows(rows)?;
+
+            let values = row
+                .values
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !hidden_indexes.contains(index))
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>();
+            Ok(send_row(sender, encode(&values), context, runtime)? && limit != Some(rows))
+        },
+    );
+    context.record_phase("evaluate", started.elapsed());
+
+    if let Some(interruption) = context.interruption() {
+        return Err(interruption_error(interruption));
+    }
+
+    result.map_err(|err| user_error("XX000", err))?;
+    Ok(rows)
+}
+
+/// Waits for room in the channel, checking the query for cancellation and
+/// timeout meanwhile. Returns `false` once the stream has been dropped.
+fn send_row<T>(
+    sender: &mpsc::Sender<PgWireResult<T>>,
+    row: PgWireResult<T>,
+    context: &QueryContext,
+    runtime: &Handle,
+) -> Result<bool, String> {
+    loop {
+        let reserved =
+            runtime.block_on(tokio::time::timeout(SEND_CHECK_INTERVAL, sender.reserve()));
+        match reserved {
+            Ok(Ok(permit)) => {
+                permit.send(row);
+                return Ok(true);
+            }
+            Ok(Err(_)) => return Ok(false),
+            Err(_) => context.check()?,
+        }
+    }
+}
+
+fn streamable_query(query: &Query) -> Option<&GQLQuery> {
+    let query = match query {
+        Query::Select(query) => query,
+        _ => return None,
+    };
+
+    if query.has_aggregation_function || query.has_group_by_statement {
+        return None;
+    }
+
+    if query
+        .statements
+        .keys()
+        .any(|name| !matches!(*name, "select" | "where" | "offset" | "limit"))
+    {
+        return None;
+    }
+
+    let select = statement::<SelectStatement>(query, "select")?;
+    if select.table_name.is_empty() || select.is_distinct || !select.alias_table.is_empty() {
+        return None;
+    }
+
+    Some(query)
+}
+
+pub fn statement<'q, T: 'static>(query: &'q GQLQuery, name: &str) -> Option<&'q T> {
+    query
+        .statements
+        .get(name)
+        .and_then(|statement| statement.as_any().downcast_ref::<T>())
+}
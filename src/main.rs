@@ -1,64 +1,228 @@
 use std;
+use std::fs;
+use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
-use async_trait::async_trait;
-
-use pgwire::api::auth::md5pass::{hash_md5_password, MakeMd5PasswordAuthStartupHandler};
-use pgwire::api::auth::{AuthSource, DefaultServerParameterProvider, LoginInfo, Password};
-use pgwire::api::MakeHandler;
-use pgwire::error::PgWireResult;
-use pgwire::tokio::process_socket;
+use clap::Parser;
+use pgwire::api::auth::DefaultServerParameterProvider;
 
 use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Semaphore};
 
+use acl::RepositoryAcl;
+use audit::AuditLog;
+use auth::{
+    hash_md5_secret, hash_scram_secret, CredentialStore, MakeGitQLStartupHandler, PeerAuthenticator,
+};
+use config::{Cli, Command, Config, LogFormat};
 use git_backend::MakeGitQLBackend;
+use server::Server;
+use session::SessionRegistry;
+use unix_socket::UnixPeers;
 
+mod acl;
+mod audit;
+mod auth;
+mod config;
+mod connection;
 mod git_backend;
+mod metrics;
+mod server;
+mod session;
+mod tls;
+mod unix_socket;
 
-struct DummyAuthSource;
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Some(Command::HashPassword { user, iterations }) = &cli.command {
+        let mut password = String::new();
+        if let Err(err) = io::stdin().read_line(&mut password) {
+            eprintln!("gql-server: cannot read password: {}", err);
+            std::process::exit(1);
+        }
 
-#[async_trait]
-impl AuthSource for DummyAuthSource {
-    async fn get_password(&self, login_info: &LoginInfo) -> PgWireResult<Password> {
-        println!("login info: {:?}", login_info);
+        let password = password.trim_end_matches(&['\r', '\n'][..]);
+        println!("[[users]]");
+        println!("name = \"{}\"", user);
+        println!("md5 = \"{}\"", hash_md5_secret(user, password));
+        println!("scram = \"{}\"", hash_scram_secret(password, *iterations));
+        return;
+    }
 
-        let salt = vec![0, 0, 0, 0];
-        let password = "pencil";
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("gql-server: {}", err);
+            std::process::exit(2);
+        }
+    };
 
-        let hash_password =
-            hash_md5_password(login_info.user().as_ref().unwrap(), password, salt.as_ref());
-        Ok(Password::new(Some(salt), hash_password.as_bytes().to_vec()))
+    let subscriber = tracing_subscriber::fmt().with_max_level(config.logging.level());
+    match config.logging.format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
     }
-}
 
-#[tokio::main]
-async fn main() {
+    let users_file = config.auth.users_file.clone().unwrap();
+    let credential_store = match CredentialStore::load(&users_file) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            tracing::error!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let reload_store = credential_store.clone();
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        while hangup.recv().await.is_some() {
+            match reload_store.reload() {
+                Ok(count) => tracing::info!("reloaded {} users from the users file", count),
+                Err(err) => tracing::error!("failed to reload the users file: {}", err),
+            }
+        }
+    });
+
+    let acl = match RepositoryAcl::new(&config.acl, credential_store.clone()) {
+        Ok(acl) => Arc::new(acl),
+        Err(err) => {
+            tracing::error!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let unix_peers = Arc::new(UnixPeers::default());
+    let peer_authenticator = if config.unix_socket.peer_auth {
+        Some(PeerAuthenticator::new(
+            unix_peers.clone(),
+            config.unix_socket.peer_map.clone(),
+        ))
+    } else {
+        None
+    };
+
+    let sessions = Arc::new(SessionRegistry::default());
     let mut parameters = DefaultServerParameterProvider::default();
-    parameters.server_version = String::from("15");
-    let authenticator = Arc::new(MakeMd5PasswordAuthStartupHandler::new(
-        Arc::new(DummyAuthSource),
-        Arc::new(parameters),
+    parameters.server_version = config.server.server_version.clone();
+    let parameters = Arc::new(parameters);
+    let authenticator = Arc::new(MakeGitQLStartupHandler::new(
+        credential_store,
+        config.auth.method,
+        config.auth.scram_iterations,
+        peer_authenticator,
+        sessions.clone(),
+        parameters.clone(),
     ));
 
-    let backend = MakeGitQLBackend::new(None);
-    let processor = Arc::new(backend);
-
-    let server_addr = "127.0.0.1:5321";
-    let listener = TcpListener::bind(server_addr).await.unwrap();
-    println!("Listening to {}", server_addr);
-    loop {
-        let incoming_socket = listener.accept().await.unwrap();
-        let authenticator_ref = authenticator.make();
-        let processor_ref = processor.make();
-        tokio::spawn(async move {
-            process_socket(
-                incoming_socket.0,
-                None,
-                authenticator_ref,
-                processor_ref.clone(),
-                processor_ref,
-            )
-            .await
-        });
+    let tls_acceptor = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => match tls::load_tls_acceptor(cert, key) {
+            Ok(acceptor) => Some(Arc::new(acceptor)),
+            Err(err) => {
+                tracing::error!("{}", err);
+                std::process::exit(2);
+            }
+        },
+        _ => None,
+    };
+
+    let audit_log = if config.audit.enabled {
+        match AuditLog::open(&config.audit) {
+            Ok(audit_log) => Some(Arc::new(audit_log)),
+            Err(err) => {
+                tracing::error!("{}", err);
+                std::process::exit(2);
+            }
+        }
+    } else {
+        None
+    };
+
+    let backend = MakeGitQLBackend::new(
+        &config.repositories.roots,
+        acl,
+        sessions.clone(),
+        config.limits.statement_timeout(),
+        Arc::new(config.query_limits),
+        audit_log,
+        parameters,
+    );
+    let (shutdown, _) = watch::channel(false);
+    let server = Arc::new(Server {
+        authenticator,
+        processor: Arc::new(backend),
+        tls_acceptor,
+        connection_limit: Arc::new(Semaphore::new(config.limits.max_connections)),
+        sessions,
+        shutdown,
+    });
+
+    let mut listeners = vec![];
+    for server_addr in &config.server.listen {
+        match TcpListener::bind(server_addr).await {
+            Ok(listener) => {
+                tracing::info!("listening on {}", server_addr);
+                listeners.push(listener);
+            }
+            Err(err) => {
+                tracing::error!("cannot listen on {}: {}", server_addr, err);
+                std::process::exit(1);
+            }
+        }
     }
+
+    for listener in listeners {
+        tokio::spawn(server.clone().serve(listener, config.tls.require));
+    }
+
+    if let Some(metrics_addr) = config.metrics.listen {
+        match TcpListener::bind(metrics_addr).await {
+            Ok(listener) => {
+                tracing::info!("serving metrics on http://{}/metrics", metrics_addr);
+                tokio::spawn(metrics::serve_metrics(listener));
+            }
+            Err(err) => {
+                tracing::error!("cannot listen on {}: {}", metrics_addr, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if config.limits.idle_timeout_secs > 0 {
+        let idle_timeout = Duration::from_secs(config.limits.idle_timeout_secs);
+        tokio::spawn(server.clone().close_idle_sessions(idle_timeout));
+    }
+
+    let mut unix_socket = None;
+    if let Some(directory) = &config.unix_socket.directory {
+        let port = config.unix_socket.port(&config.server.listen);
+        let socket_path = unix_socket::socket_path(directory, port);
+        let unix_listener = match unix_socket::bind_unix_socket(&socket_path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("{}", err);
+                std::process::exit(1);
+            }
+        };
+
+        tracing::info!("listening on {}", socket_path.display());
+        tokio::spawn(server.clone().serve_unix(unix_listener, unix_peers));
+        unix_socket = Some(socket_path);
+    }
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    tracing::info!("shutting down, waiting for running queries to finish");
+    if let Some(socket_path) = &unix_socket {
+        let _ = fs::remove_file(socket_path);
+    }
+
+    let grace_period = Duration::from_secs(config.limits.shutdown_grace_secs);
+    server.shutdown(grace_period).await;
 }
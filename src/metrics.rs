@@ -0,0 +1,107 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::server::ACCEPT_RETRY_DELAY;
+
+lazy_static! {
+    pub static ref ACTIVE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "gql_active_connections",
+        "Number of currently open client sessions"
+    )
+    .unwrap();
+    pub static ref QUERIES: IntCounterVec = register_int_counter_vec!(
+        "gql_queries_total",
+        "Number of executed queries by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "gql_query_duration_seconds",
+        "Query latency by table touched",
+        &["table"]
+    )
+    .unwrap();
+    pub static ref COMMITS_WALKED: IntCounter = register_int_counter!(
+        "gql_commits_walked_total",
+        "Number of commits traversed by revwalks"
+    )
+    .unwrap();
+    pub static ref DIFF_COMPUTATIONS: IntCounter = register_int_counter!(
+        "gql_diff_computations_total",
+        "Number of tree diffs computed for the diffs table"
+    )
+    .unwrap();
+    pub static ref QUERY_CACHE: IntCounterVec = register_int_counter_vec!(
+        "gql_query_cache_requests_total",
+        "Lookups of described portals in the query cache by result",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref AUTH_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "gql_auth_failures_total",
+        "Number of failed authentication attempts by method",
+        &["method"]
+    )
+    .unwrap();
+}
+
+pub async fn serve_metrics(listener: TcpListener) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                tracing::warn!("failed to accept metrics connection: {}", err);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        tokio::spawn(handle_metrics_request(socket));
+    }
+}
+
+async fn handle_metrics_request(mut socket: TcpStream) {
+    let mut buffer = [0u8; 1024];
+    let read = match socket.read(&mut buffer).await {
+        Ok(read) => read,
+        Err(_) => return,
+    };
+
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let encoder = TextEncoder::new();
+            let mut body = vec![];
+            if encoder.encode(&prometheus::gather(), &mut body).is_err() {
+                return;
+            }
+            http_response("200 OK", encoder.format_type(), &body)
+        }
+        _ => http_response("404 Not Found", "text/plain", b"not found\n"),
+    };
+
+    let _ = socket.write_all(&response).await;
+    let _ = socket.shutdown().await;
+}
+
+fn http_response(status: &str, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
@@ -0,0 +1,264 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use pgwire::api::MakeHandler;
+use pgwire::error::ErrorInfo;
+use pgwire::messages::response::ErrorResponse;
+use pgwire::messages::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{watch, Semaphore};
+use tokio_rustls::TlsAcceptor;
+
+use crate::auth::MakeGitQLStartupHandler;
+use crate::connection::process_connection;
+use crate::git_backend::MakeGitQLBackend;
+use crate::session::SessionRegistry;
+use crate::unix_socket::{peer_os_user, UnixPeers};
+
+const SSL_REQUEST_CODE: i32 = 80877103;
+const CANCEL_REQUEST_CODE: i32 = 80877102;
+/// Pause after a failed accept, so that running out of file descriptors does
+/// not turn a listener loop into a busy loop.
+pub const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Server {
+    pub authenticator: Arc<MakeGitQLStartupHandler>,
+    pub processor: Arc<MakeGitQLBackend>,
+    pub tls_acceptor: Option<Arc<TlsAcceptor>>,
+    pub connection_limit: Arc<Semaphore>,
+    pub sessions: Arc<SessionRegistry>,
+    pub shutdown: watch::Sender<bool>,
+}
+
+impl Server {
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, require_tls: bool) {
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            let (socket, addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("failed to accept connection: {}", err);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                },
+                _ = shutdown.changed() => break,
+            };
+
+            tokio::spawn(self.clone().admit_connection(socket, addr, require_tls));
+        }
+    }
+
+    /// Negotiates SSL and handles a CancelRequest outside the connection
+    /// limit, as it is sent on a connection of its own that never becomes a
+    /// session, before admitting the client as a session.
+    async fn admit_connection(
+        self: Arc<Self>,
+        mut socket: TcpStream,
+        addr: SocketAddr,
+        require_tls: bool,
+    ) {
+        let mut header = [0u8; 8];
+        if socket.read_exact(&mut header).await.is_err() {
+            return;
+        }
+
+        if let (StartupRequest::Ssl, Some(tls_acceptor)) =
+            (startup_request(&header), &self.tls_acceptor)
+        {
+            if socket.write_all(b"S").await.is_err() {
+                return;
+            }
+            let mut socket = match tls_acceptor.accept(socket).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    tracing::debug!("TLS handshake with {} failed: {}", addr, err);
+                    return;
+                }
+            };
+            if socket.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            if let Some(startup) = self.startup_packet(&mut socket, header).await {
+                self.run_session(socket, addr, true, startup).await;
+            }
+            return;
+        }
+
+        let Some(startup) = self.startup_packet(&mut socket, header).await else {
+            return;
+        };
+        if require_tls {
+            reject_connection(socket, "28000", "SSL connection is required").await;
+            return;
+        }
+        self.run_session(socket, addr, false, startup).await;
+    }
+
+    /// Accepts clients on the unix socket and serves the stream directly.
+    /// As with PostgreSQL's local connections, TLS is neither offered nor
+    /// required there; access is governed by the socket file permissions.
+    pub async fn serve_unix(self: Arc<Self>, listener: UnixListener, peers: Arc<UnixPeers>) {
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            let socket = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((socket, _)) => socket,
+                    Err(err) => {
+                        tracing::warn!("failed to accept unix socket connection: {}", err);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                },
+                _ = shutdown.changed() => break,
+            };
+
+            tokio::spawn(self.clone().admit_unix_connection(socket, peers.clone()));
+        }
+    }
+
+    async fn admit_unix_connection(self: Arc<Self>, mut socket: UnixStream, peers: Arc<UnixPeers>) {
+        let mut header = [0u8; 8];
+        if socket.read_exact(&mut header).await.is_err() {
+            return;
+        }
+        let Some(startup) = self.startup_packet(&mut socket, header).await else {
+            return;
+        };
+
+        let addr = peers.register();
+        if let Some(os_user) = peer_os_user(&socket).await {
+            peers.set_os_user(addr, os_user);
+        }
+        self.run_session(socket, addr, false, startup).await;
+        peers.remove(&addr);
+    }
+
+    /// Declines SSL, which is only offered before the TLS handshake, and
+    /// handles a CancelRequest, returning the header of the startup packet
+    /// once the client sends one.
+    async fn startup_packet<S>(&self, socket: &mut S, mut header: [u8; 8]) -> Option<BytesMut>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        loop {
+            match startup_request(&header) {
+                StartupRequest::Ssl => socket.write_all(b"N").await.ok()?,
+                StartupRequest::Cancel => {
+                    let mut key = [0u8; 8];
+                    if socket.read_exact(&mut key).await.is_ok() {
+                        self.cancel(&key);
+                    }
+                    return None;
+                }
+                StartupRequest::Other => return Some(BytesMut::from(&header[..])),
+            }
+            socket.read_exact(&mut header).await.ok()?;
+        }
+    }
+
+    /// Serves the client as a session when a connection slot is free.
+    async fn run_session<S>(&self, socket: S, addr: SocketAddr, is_secure: bool, startup: BytesMut)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let Ok(_permit) = self.connection_limit.clone().try_acquire_owned() else {
+            reject_connection(socket, "53300", "sorry, too many clients already").await;
+            return;
+        };
+
+        let registration = self.sessions.register(addr);
+        process_connection(
+            socket,
+            addr,
+            is_secure,
+            startup,
+            registration.close_signal(),
+            self.authenticator.make(),
+            self.processor.make(),
+        )
+        .await;
+    }
+
+    /// Cancels the running query of the session identified by the process id
+    /// and secret key of a CancelRequest.
+    fn cancel(&self, key: &[u8]) {
+        let pid = i32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+        let secret = i32::from_be_bytes([key[4], key[5], key[6], key[7]]);
+        if !self.sessions.cancel(pid, secret) {
+            tracing::debug!(
+                pid,
+                "ignoring cancel request without a matching running query"
+            );
+        }
+    }
+
+    pub async fn close_idle_sessions(self: Arc<Self>, idle_timeout: Duration) {
+        let mut shutdown = self.shutdown.subscribe();
+        let interval = idle_timeout.min(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    let closed = self.sessions.close_idle_sessions(idle_timeout);
+                    if closed > 0 {
+                        tracing::info!("closed {} idle sessions", closed);
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    pub async fn shutdown(&self, grace_period: Duration) {
+        self.shutdown.send_replace(true);
+        let deadline = Instant::now() + grace_period;
+        loop {
+            self.sessions.close_sessions_without_queries();
+            if self.sessions.is_empty() {
+                return;
+            }
+
+            if Instant::now() >= deadline {
+                let closed = self.sessions.close_all_sessions();
+                tracing::warn!("grace period expired, closed {} busy sessions", closed);
+                return;
+            }
+
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+}
+
+enum StartupRequest {
+    Ssl,
+    Cancel,
+    Other,
+}
+
+/// Classifies the first packet of a connection from its length and code.
+fn startup_request(header: &[u8; 8]) -> StartupRequest {
+    let length = i32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let code = i32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    match (length, code) {
+        (8, SSL_REQUEST_CODE) => StartupRequest::Ssl,
+        (16, CANCEL_REQUEST_CODE) => StartupRequest::Cancel,
+        _ => StartupRequest::Other,
+    }
+}
+
+async fn reject_connection<S>(mut socket: S, code: &str, message: &str)
+where
+    S: AsyncWrite + Unpin,
+{
+    let error_info = ErrorInfo::new("FATAL".to_owned(), code.to_owned(), message.to_owned());
+    let mut buffer = BytesMut::new();
+    if ErrorResponse::from(error_info).encode(&mut buffer).is_ok() {
+        let _ = socket.write_all(&buffer).await;
+    }
+    let _ = socket.shutdown().await;
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::git_backend::QueryContext;
+use crate::metrics;
+
+pub struct Session {
+    pub pid: i32,
+    pub secret: i32,
+    last_activity: Mutex<Instant>,
+    active_queries: AtomicUsize,
+    current_query: Mutex<Option<Arc<QueryContext>>>,
+}
+
+impl Session {
+    fn new(pid: i32) -> Session {
+        Session {
+            pid,
+            secret: rand::random::<i32>(),
+            last_activity: Mutex::new(Instant::now()),
+            active_queries: AtomicUsize::new(0),
+            current_query: Mutex::new(None),
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.active_queries.load(Ordering::SeqCst) > 0
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+}
+
+pub struct QueryGuard {
+    session: Arc<Session>,
+}
+
+impl Drop for QueryGuard {
+    fn drop(&mut self) {
+        self.session.current_query.lock().unwrap().take();
+        self.session.active_queries.fetch_sub(1, Ordering::SeqCst);
+        self.session.touch();
+    }
+}
+
+/// Why the server closes a session, reported to the client as a FATAL error
+/// before the connection is closed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseReason {
+    IdleTimeout,
+    Shutdown,
+}
+
+impl CloseReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CloseReason::IdleTimeout => "57P05",
+            CloseReason::Shutdown => "57P01",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            CloseReason::IdleTimeout => "terminating connection due to idle-session timeout",
+            CloseReason::Shutdown => "terminating connection due to administrator command",
+        }
+    }
+}
+
+struct SessionEntry {
+    session: Arc<Session>,
+    close: watch::Sender<Option<CloseReason>>,
+}
+
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_pid: AtomicI32,
+    sessions: Mutex<HashMap<SocketAddr, SessionEntry>>,
+}
+
+impl SessionRegistry {
+    pub fn register(self: &Arc<Self>, addr: SocketAddr) -> SessionRegistration {
+        let pid = self.next_pid.fetch_add(1, Ordering::SeqCst) + 1;
+        let (close, close_signal) = watch::channel(None);
+        let entry = SessionEntry {
+            session: Arc::new(Session::new(pid)),
+            close,
+        };
+        self.sessions.lock().unwrap().insert(addr, entry);
+        metrics::ACTIVE_CONNECTIONS.inc();
+
+        SessionRegistration {
+            registry: self.clone(),
+            addr,
+            close_signal,
+        }
+    }
+
+    pub fn session(&self, addr: &SocketAddr) -> Option<Arc<Session>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(addr)
+            .map(|entry| entry.session.clone())
+    }
+
+    pub fn begin_query(&self, addr: &SocketAddr, context: Arc<QueryContext>) -> Option<QueryGuard> {
+        let session = self.session(addr)?;
+        *session.current_query.lock().unwrap() = Some(context);
+        session.active_queries.fetch_add(1, Ordering::SeqCst);
+        session.touch();
+        Some(QueryGuard { session })
+    }
+
+    pub fn cancel(&self, pid: i32, secret: i32) -> bool {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .find(|entry| entry.session.pid == pid && entry.session.secret == secret)
+            .map(|entry| entry.session.clone());
+
+        let context = session.and_then(|session| session.current_query.lock().unwrap().clone());
+        match context {
+            Some(context) => {
+                context.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.lock().unwrap().is_empty()
+    }
+
+    pub fn close_idle_sessions(&self, idle_timeout: Duration) -> usize {
+        self.close_sessions(CloseReason::IdleTimeout, |session| {
+            !session.is_busy() && session.idle_for() >= idle_timeout
+        })
+    }
+
+    pub fn close_sessions_without_queries(&self) -> usize {
+        self.close_sessions(CloseReason::Shutdown, |session| !session.is_busy())
+    }
+
+    pub fn close_all_sessions(&self) -> usize {
+        self.close_sessions(CloseReason::Shutdown, |_| true)
+    }
+
+    /// Signals the connections of the matching sessions to report `reason`
+    /// and close, cancelling the query any of them is running.
+    fn close_sessions<F>(&self, reason: CloseReason, should_close: F) -> usize
+    where
+        F: Fn(&Session) -> bool,
+    {
+        let sessions = self.sessions.lock().unwrap();
+        let mut closed = 0;
+        for entry in sessions.values() {
+            if !should_close(&entry.session) || entry.close.borrow().is_some() {
+                continue;
+            }
+
+            if let Some(context) = entry.session.current_query.lock().unwrap().as_ref() {
+                context.cancel();
+            }
+            entry.close.send_replace(Some(reason));
+            closed += 1;
+        }
+        closed
+    }
+
+    fn unregister(&self, addr: &SocketAddr) {
+        if self.sessions.lock().unwrap().remove(addr).is_some() {
+            metrics::ACTIVE_CONNECTIONS.dec();
+        }
+    }
+}
+
+pub struct SessionRegistration {
+    registry: Arc<SessionRegistry>,
+    addr: SocketAddr,
+    close_signal: watch::Receiver<Option<CloseReason>>,
+}
+
+impl SessionRegistration {
+    /// Receives the reason once the server decides to close the session.
+    pub fn close_signal(&self) -> watch::Receiver<Option<CloseReason>> {
+        self.close_signal.clone()
+    }
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        self.registry.unregister(&self.addr);
+    }
+}
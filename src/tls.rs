@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+pub fn load_tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, String> {
+    let cert_file = File::open(cert_path).map_err(|err| {
+        format!(
+            "cannot read TLS certificate {}: {}",
+            cert_path.display(),
+            err
+        )
+    })?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid TLS certificate {}: {}", cert_path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", cert_path.display()));
+    }
+
+    let key_file = File::open(key_path)
+        .map_err(|err| format!("cannot read TLS key {}: {}", key_path.display(), err))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|err| format!("invalid TLS key {}: {}", key_path.display(), err))?
+        .ok_or_else(|| format!("no private key found in {}", key_path.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("cannot use TLS certificate and key: {}", err))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
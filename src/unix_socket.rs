@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tokio::net::{UnixListener, UnixStream};
+
+/// Unix socket clients have no network address, so each one is given an
+/// address from the IPv6 discard-only prefix `100::/64`, which no TCP peer
+/// can have, for sessions and the audit log to key on.
+const LOCAL_ADDR_PREFIX: u128 = 0x0100 << 112;
+
+#[derive(Default)]
+pub struct UnixPeers {
+    next_id: AtomicU64,
+    peers: Mutex<HashMap<SocketAddr, String>>,
+}
+
+impl UnixPeers {
+    pub fn os_user(&self, addr: &SocketAddr) -> Option<String> {
+        self.peers.lock().unwrap().get(addr).cloned()
+    }
+
+    /// Assigns an address to a new unix socket client.
+    pub fn register(&self) -> SocketAddr {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let ip = Ipv6Addr::from(LOCAL_ADDR_PREFIX | u128::from(id));
+        SocketAddr::new(IpAddr::V6(ip), 0)
+    }
+
+    pub fn set_os_user(&self, addr: SocketAddr, os_user: String) {
+        self.peers.lock().unwrap().insert(addr, os_user);
+    }
+
+    pub fn remove(&self, addr: &SocketAddr) {
+        self.peers.lock().unwrap().remove(addr);
+    }
+}
+
+/// Tells whether `addr` was assigned to a unix socket client by
+/// [`UnixPeers::register`].
+pub fn is_local_addr(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V6(ip) => u128::from(ip) >> 64 == LOCAL_ADDR_PREFIX >> 64,
+        IpAddr::V4(_) => false,
+    }
+}
+
+pub fn socket_path(directory: &Path, port: u16) -> PathBuf {
+    directory.join(format!(".s.PGSQL.{}", port))
+}
+
+/// Binds the socket, replacing a socket file left behind by a server that is
+/// no longer running but never one another server still listens on.
+pub fn bind_unix_socket(path: &Path) -> Result<UnixListener, String> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+
+        match StdUnixStream::connect(path) {
+            Ok(_) => {
+                return Err(format!(
+                    "another server is already listening on {}",
+                    path.display()
+                ))
+            }
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                fs::remove_file(path).map_err(|err| {
+                    format!("cannot remove stale socket {}: {}", path.display(), err)
+                })?;
+            }
+            Err(err) => {
+                return Err(format!("cannot check socket {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    UnixListener::bind(path).map_err(|err| format!("cannot listen on {}: {}", path.display(), err))
+}
+
+/// Looks up the OS user on the other end of the socket. The lookup goes
+/// through NSS and may block, so it runs on the blocking pool.
+pub async fn peer_os_user(stream: &UnixStream) -> Option<String> {
+    let uid = stream.peer_cred().ok()?.uid();
+    tokio::task::spawn_blocking(move || os_user_name(uid))
+        .await
+        .ok()
+        .flatten()
+}
+
+fn os_user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: `passwd` and `buffer` outlive the call, and `pw_name` points
+        // into `buffer`, which is only read while it is still alive.
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let status = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if status == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        if status != 0 || result.is_null() {
+            return None;
+        }
+
+        let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+        return name.to_str().ok().map(str::to_owned);
+    }
+}